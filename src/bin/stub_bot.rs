@@ -0,0 +1,98 @@
+use std::io;
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+// a stand in for an engine speaking the tetris bot protocol, for trying out the game's side of it. it
+// drops the first piece in the queue wherever its top ends up lowest, and never holds or spins. set
+// bot.command to ["target/debug/stub_bot"] to play against it
+fn main() -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    send(&mut stdout, json!({ "type": "info", "name": "stub", "version": "0.1.0", "author": "tetrust", "features": [] }))?;
+
+    let mut game: Option<(Vec<Vec<bool>>, char)> = None;
+    for line in stdin.lock().lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let message: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+        match message["type"].as_str() {
+            Some("rules") => send(&mut stdout, json!({ "type": "ready" }))?,
+            Some("start") => {
+                let board = message["board"].as_array()
+                    .map(|rows| rows.iter().map(|row| row.as_array().map_or(vec![], |cells| cells.iter().map(|c| !c.is_null()).collect())).collect())
+                    .unwrap_or_default();
+                let piece = message["queue"][0].as_str().and_then(|name| name.chars().next());
+                game = piece.map(|piece| (board, piece));
+            }
+            // the game starts afresh for every piece, so there's nothing to keep up with between them
+            Some("play") | Some("new_piece") => {}
+            Some("suggest") => {
+                let moves: Vec<Value> = game.as_ref().and_then(|(board, piece)| placement(board, *piece)).into_iter().collect();
+                send(&mut stdout, json!({ "type": "suggestion", "moves": moves }))?;
+            }
+            Some("stop") => game = None,
+            Some("quit") => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn send(stdout: &mut io::Stdout, message: Value) -> Result<(), String> {
+    writeln!(stdout, "{}", message).and_then(|_| stdout.flush()).map_err(|e| e.to_string())
+}
+
+// every column and orientation dropped straight down from the top of the board
+fn placement(board: &[Vec<bool>], piece: char) -> Option<Value> {
+    let width = board.first().map_or(0, |row| row.len()) as i32;
+    let mut best: Option<(i32, Value)> = None;
+    for &orientation in ORIENTATIONS.iter() {
+        for x in -2..width + 2 {
+            let mut y = board.len() as i32 - 3;
+            if !fits(board, piece, orientation, x, y) {
+                continue;
+            }
+            while fits(board, piece, orientation, x, y - 1) {
+                y -= 1;
+            }
+            let top = cells(piece, orientation, x, y).iter().map(|c| c.1).max().unwrap_or(y);
+            if best.as_ref().is_none_or(|(lowest, _)| top < *lowest) {
+                let location = json!({ "type": piece.to_string(), "orientation": orientation, "x": x, "y": y });
+                best = Some((top, json!({ "location": location, "spin": "none" })));
+            }
+        }
+    }
+    best.map(|(_, m)| m)
+}
+
+fn fits(board: &[Vec<bool>], piece: char, orientation: &str, x: i32, y: i32) -> bool {
+    let cells = cells(piece, orientation, x, y);
+    !cells.is_empty() && cells.iter().all(|&(x, y)| {
+        y >= 0 && (y as usize) < board.len() && x >= 0 && (x as usize) < board[y as usize].len() && !board[y as usize][x as usize]
+    })
+}
+
+// y counts up from the floor, as the protocol has it
+fn cells(piece: char, orientation: &str, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let north = match piece {
+        'I' => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        'T' => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        'L' => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        'J' => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        'S' => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        'Z' => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+        _ => return vec![],
+    };
+    north.iter()
+        .map(|&(dx, dy)| match orientation {
+            "east" => (dy, -dx),
+            "south" => (-dx, -dy),
+            "west" => (-dy, dx),
+            _ => (dx, dy),
+        })
+        .map(|(dx, dy)| (x + dx, y + dy))
+        .collect()
+}
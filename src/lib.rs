@@ -0,0 +1,3 @@
+// the game lives in a library so the binary and the integration tests can both use it
+#[path = "lib/mod.rs"]
+pub mod lib;
@@ -1,142 +1,1238 @@
+use std::path::Path;
 use std::sync::mpsc::SyncSender;
 
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use chrono::Local;
+use sdl2::clipboard::ClipboardUtil;
+use sdl2::controller::Button;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
 use sdl2::ttf::Font;
 
-use crate::lib::GameState;
+use rand::{Rng, thread_rng};
 
-#[derive(PartialEq)]
+use crate::lib::{master_grade, Action, Bot, Controls, GameState, GarbageGenerator, LeaderboardEntry, Leaderboards, MAX_FIRST_TO, MAX_START_LEVEL, Menu, MenuInput, Mode, Opener, OptionsMenu,
+                 OptionsOutcome, PAUSE_KEYS, PieceSet, Puzzle, PuzzlePack, PuzzleProgress, Ranking, Replay, ReplayInput, ReplayPlayer, ReplayRecorder, SaveFile, Settings, SETTINGS_PATH, StatField,
+                 Versus};
+use crate::lib::text::draw_text;
+
+const SAVE_PATH: &str = "save.json";
+const LEADERBOARD_PATH: &str = "leaderboards.json";
+const REPLAY_DIR: &str = "replays";
+const PUZZLE_DIR: &str = "puzzles";
+const PROGRESS_PATH: &str = "puzzle_progress.json";
+const OPENER_DIR: &str = "openers";
+const AUTOSAVE_INTERVAL: u64 = 10;
+const MAX_NAME_LENGTH: usize = 12;
+const COUNTDOWN_FRAMES: u64 = 180;
+const VERSUS_PLAYERS: usize = 2;
+const COOP_PLAYERS: usize = 2;
+const COOP_WIDTH: usize = 20;
+// pasted boards are played in zen whatever mode was picked last, so no goal or second seat gets in the way
+const PASTE_MODE: Mode = Mode::Zen { gravity: true };
+
+#[derive(Clone, Copy, PartialEq)]
 enum GuiState {
-    Menu,
+    Title,
+    ModeSelect,
+    Options,
+    Controls,
+    HighScores,
+    Replays,
+    Replay,
+    Packs,
+    Puzzles,
+    Openers,
+    VersusSetup,
     Game,
+    Versus,
+    RoundOver,
+    Countdown,
+    Paused,
+    NameEntry,
+    Victory,
     Lost,
+    Demo,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MenuAction {
+    Continue,
+    Play,
+    Start(Mode),
+    Options,
+    Controls,
+    HighScores,
+    Replays,
+    Watch(usize),
+    Puzzles,
+    Pack(usize),
+    Puzzle(usize),
+    NextPuzzle,
+    PuzzleList,
+    Openers,
+    Opener(usize, bool),
+    Versus,
+    StartVersus,
+    NextRound,
+    Resume,
+    Restart,
+    MainMenu,
+    Quit,
 }
 
 pub enum Sound {
     Clear,
     Ground,
     End,
+    Warning,
+}
+
+// the title screen's attract mode, with the bot playing an endless marathon
+struct Demo {
+    game_state: GameState,
+    bot: Bot,
+}
+
+struct Watching {
+    player: ReplayPlayer,
+    game_state: GameState,
+    score: u64,
+    finished: bool,
 }
 
 pub struct AppState {
     gui_state: GuiState,
+    back_to: GuiState,
+    menu: Menu<MenuAction>,
     game_state: GameState,
+    mode: Mode,
+    board_mode: Mode,
+    start_level: u64,
+    in_progress: bool,
+    countdown: u64,
     score: u64,
-    high_score: u64,
+    autosaved_at: u64,
+    recorder: ReplayRecorder,
+    replays: Vec<Replay>,
+    watching: Option<Watching>,
+    demo: Option<Demo>,
+    idle: u64,
+    bot: Option<Bot>,
+    packs: Vec<PuzzlePack>,
+    puzzle: (usize, usize),
+    progress: PuzzleProgress,
+    openers: Vec<Opener>,
+    opener: (usize, bool),
+    versus: Option<Versus>,
+    first_to: u64,
+    leaderboards: Leaderboards,
+    player_name: String,
+    settings: Settings,
+    controls: Controls,
+    partner: Controls,
+    options_menu: OptionsMenu,
+    clipboard: ClipboardUtil,
 }
 
 impl AppState {
-    pub fn new() -> AppState {
-        AppState {
-            gui_state: GuiState::Menu,
-            game_state: GameState::new(),
+    pub fn new(settings: Settings, clipboard: ClipboardUtil) -> AppState {
+        let game_state = new_game(Mode::ALL[0], &settings);
+        let mut app_state = AppState {
+            gui_state: GuiState::Title,
+            back_to: GuiState::Title,
+            menu: Menu::new("", vec![]),
+            recorder: ReplayRecorder::new(&game_state),
+            game_state,
+            mode: Mode::ALL[0],
+            board_mode: Mode::ALL[0],
+            start_level: 1,
+            in_progress: false,
+            countdown: 0,
             score: 0,
-            high_score: 0,
+            autosaved_at: 0,
+            replays: vec![],
+            watching: None,
+            demo: None,
+            idle: 0,
+            bot: None,
+            packs: vec![],
+            puzzle: (0, 0),
+            progress: PuzzleProgress::default(),
+            openers: vec![],
+            opener: (0, false),
+            versus: None,
+            first_to: settings.versus.first_to,
+            leaderboards: Leaderboards::default(),
+            player_name: String::new(),
+            settings,
+            controls: Controls::new(),
+            partner: Controls::new(),
+            options_menu: OptionsMenu::new(),
+            clipboard,
+        };
+
+        match Leaderboards::load(Path::new(LEADERBOARD_PATH)) {
+            Ok(leaderboards) => app_state.leaderboards = leaderboards,
+            Err(e) => eprintln!("failed to load leaderboards: {}", e),
+        }
+
+        match PuzzleProgress::load(Path::new(PROGRESS_PATH)) {
+            Ok(progress) => app_state.progress = progress,
+            Err(e) => eprintln!("failed to load puzzle progress: {}", e),
+        }
+
+        match SaveFile::load(Path::new(SAVE_PATH)) {
+            Ok(Some(save)) => {
+                app_state.start(save.mode, save.game_state);
+                app_state.score = save.score;
+                app_state.autosaved_at = app_state.game_state.pieces();
+            }
+            Ok(None) => (),
+            Err(e) => eprintln!("failed to load saved game: {}", e),
+        }
+
+        app_state.enter(GuiState::Title);
+        app_state
+    }
+
+    fn start(&mut self, mode: Mode, game_state: GameState) {
+        self.recorder = ReplayRecorder::new(&game_state);
+        self.game_state = game_state;
+        self.mode = mode;
+        self.in_progress = true;
+        self.score = 0;
+        self.autosaved_at = 0;
+        self.controls.reset();
+        self.partner.reset();
+        self.bot = if self.settings.bot.enabled { Some(Bot::new(&self.settings)) } else { None };
+    }
+
+    fn enter(&mut self, gui_state: GuiState) {
+        let items = match gui_state {
+            GuiState::Title => {
+                self.idle = 0;
+                let mut items = vec![];
+                if self.in_progress {
+                    items.push(("Continue".to_string(), MenuAction::Continue));
+                }
+                items.push(("Play".to_string(), MenuAction::Play));
+                items.push(("Puzzles".to_string(), MenuAction::Puzzles));
+                items.push(("Openers".to_string(), MenuAction::Openers));
+                items.push(("Versus".to_string(), MenuAction::Versus));
+                items.push(("Co-op".to_string(), MenuAction::Start(Mode::Coop)));
+                items.push(("Options".to_string(), MenuAction::Options));
+                items.push(("Controls".to_string(), MenuAction::Controls));
+                items.push(("High scores".to_string(), MenuAction::HighScores));
+                items.push(("Replays".to_string(), MenuAction::Replays));
+                items.push(("Quit".to_string(), MenuAction::Quit));
+                Menu::new("tetrust", items)
+            }
+            GuiState::ModeSelect => {
+                let items = Mode::ALL.iter()
+                    .map(|&mode| {
+                        let mode = mode.with_start_level(self.start_level);
+                        match mode {
+                            Mode::Marathon { start_level, .. } => (format!("{} < level {} >", mode.name(), start_level), MenuAction::Start(mode)),
+                            _ => (mode.name(), MenuAction::Start(mode)),
+                        }
+                    })
+                    .collect();
+                Menu::new("Select mode", items)
+            }
+            GuiState::Replays => {
+                self.replays = Replay::load_all(Path::new(REPLAY_DIR));
+                let items = self.replays.iter()
+                    .enumerate()
+                    .map(|(i, r)| (format!("{} {} {}", r.date.format("%Y-%m-%d %H:%M"), r.mode.name(), r.score), MenuAction::Watch(i)))
+                    .collect();
+                Menu::new("Replays", items)
+            }
+            GuiState::Packs => {
+                self.packs = PuzzlePack::load_all(Path::new(PUZZLE_DIR));
+                let items = self.packs.iter()
+                    .enumerate()
+                    .map(|(i, pack)| (format!("{} {}/{}", pack.name, self.progress.solved(pack), pack.puzzles.len()), MenuAction::Pack(i)))
+                    .collect();
+                Menu::new("Puzzles", items)
+            }
+            GuiState::Puzzles => {
+                let pack = &self.packs[self.puzzle.0];
+                let items = pack.puzzles.iter()
+                    .enumerate()
+                    .map(|(i, puzzle)| {
+                        let mark = if self.progress.is_solved(&pack.name, &puzzle.name) { 'x' } else { ' ' };
+                        (format!("[{}] {}", mark, puzzle.name), MenuAction::Puzzle(i))
+                    })
+                    .collect();
+                Menu::new(&pack.name, items)
+            }
+            GuiState::Openers => {
+                self.openers = Opener::load_all(Path::new(OPENER_DIR));
+                let items = self.openers.iter()
+                    .enumerate()
+                    .flat_map(|(i, opener)| vec![
+                        (opener.name.clone(), MenuAction::Opener(i, false)),
+                        (format!("{} mirrored", opener.name), MenuAction::Opener(i, true)),
+                    ])
+                    .collect();
+                Menu::new("Openers", items)
+            }
+            GuiState::VersusSetup => Menu::new("Versus", vec![
+                (format!("First to {} < >", self.first_to), MenuAction::StartVersus),
+            ]),
+            GuiState::RoundOver => {
+                let versus = self.versus.as_ref().expect("a round only ends in versus");
+                match versus.champion() {
+                    Some(champion) => Menu::new(&format!("Player {} wins the match!", champion + 1), vec![
+                        ("Rematch".to_string(), MenuAction::StartVersus),
+                        ("Main menu".to_string(), MenuAction::MainMenu),
+                    ]),
+                    None => {
+                        let title = match versus.winner() {
+                            Some(winner) => format!("Player {} takes round {}", winner + 1, versus.round()),
+                            None => format!("Round {} is a draw", versus.round()),
+                        };
+                        Menu::new(&title, vec![
+                            ("Next round".to_string(), MenuAction::NextRound),
+                            ("Main menu".to_string(), MenuAction::MainMenu),
+                        ])
+                    }
+                }
+            }
+            GuiState::Victory if self.mode == Mode::Puzzle => {
+                let mut items = vec![];
+                if self.packs[self.puzzle.0].puzzles.len() > self.puzzle.1 + 1 {
+                    items.push(("Next puzzle".to_string(), MenuAction::NextPuzzle));
+                }
+                items.push(("Retry".to_string(), MenuAction::Restart));
+                items.push(("Puzzle list".to_string(), MenuAction::PuzzleList));
+                Menu::new("Solved!", items)
+            }
+            GuiState::Lost if self.mode == Mode::Puzzle => Menu::new("Failed", vec![
+                ("Retry".to_string(), MenuAction::Restart),
+                ("Puzzle list".to_string(), MenuAction::PuzzleList),
+            ]),
+            GuiState::Paused => Menu::new("Paused", vec![
+                ("Resume".to_string(), MenuAction::Resume),
+                ("Restart".to_string(), MenuAction::Restart),
+                ("Options".to_string(), MenuAction::Options),
+                ("Main menu".to_string(), MenuAction::MainMenu),
+                ("Quit".to_string(), MenuAction::Quit),
+            ]),
+            GuiState::HighScores => {
+                self.board_mode = if self.mode.ranking().is_some() { self.mode } else { Mode::ALL[0] };
+                Menu::new("", vec![])
+            }
+            GuiState::Victory => Menu::new("Victory!", vec![
+                ("Play again".to_string(), MenuAction::Restart),
+                ("Main menu".to_string(), MenuAction::MainMenu),
+            ]),
+            GuiState::Lost => Menu::new("Game over", vec![
+                ("Retry".to_string(), MenuAction::Restart),
+                ("Main menu".to_string(), MenuAction::MainMenu),
+            ]),
+            _ => Menu::new("", vec![]),
+        };
+        self.menu = items;
+        self.gui_state = gui_state;
+    }
+
+    fn perform(&mut self, action: MenuAction) -> bool {
+        match action {
+            MenuAction::Continue
+            | MenuAction::Resume => self.resume(),
+            MenuAction::Play => self.enter(GuiState::ModeSelect),
+            MenuAction::Start(mode) => {
+                self.start(mode, new_game(mode, &self.settings));
+                self.enter(GuiState::Game);
+            }
+            MenuAction::Restart if self.mode == Mode::Puzzle => self.start_puzzle(self.puzzle.1),
+            MenuAction::Restart if self.mode == Mode::Versus => self.start_versus(),
+            MenuAction::Restart if self.mode == Mode::Opener => self.start_opener(),
+            MenuAction::Restart => {
+                self.start(self.mode, new_game(self.mode, &self.settings));
+                self.enter(GuiState::Game);
+            }
+            MenuAction::Puzzles => self.enter(GuiState::Packs),
+            MenuAction::Pack(i) => {
+                self.puzzle = (i, 0);
+                self.enter(GuiState::Puzzles);
+            }
+            MenuAction::Puzzle(i) => self.start_puzzle(i),
+            MenuAction::NextPuzzle => self.start_puzzle(self.puzzle.1 + 1),
+            MenuAction::PuzzleList => {
+                self.enter(GuiState::Puzzles);
+                self.menu.select(self.puzzle.1);
+            }
+            MenuAction::Openers => self.enter(GuiState::Openers),
+            MenuAction::Opener(i, mirrored) => {
+                self.opener = (i, mirrored);
+                self.start_opener();
+            }
+            MenuAction::Versus => self.enter(GuiState::VersusSetup),
+            MenuAction::StartVersus => self.start_versus(),
+            MenuAction::NextRound => self.next_round(),
+            MenuAction::Options
+            | MenuAction::Controls
+            | MenuAction::HighScores => {
+                self.back_to = self.gui_state;
+                self.enter(match action {
+                    MenuAction::Options => GuiState::Options,
+                    MenuAction::Controls => GuiState::Controls,
+                    _ => GuiState::HighScores,
+                });
+            }
+            MenuAction::Replays => self.enter(GuiState::Replays),
+            MenuAction::Watch(i) => {
+                if i < self.replays.len() {
+                    let (player, game_state) = ReplayPlayer::new(self.replays.remove(i));
+                    self.watching = Some(Watching { player, game_state, score: 0, finished: false });
+                    self.enter(GuiState::Replay);
+                }
+            }
+            MenuAction::MainMenu => {
+                self.versus = None;
+                self.enter(GuiState::Title);
+            }
+            MenuAction::Quit => {
+                self.save();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn current_puzzle(&self) -> Option<&Puzzle> {
+        self.packs.get(self.puzzle.0).and_then(|pack| pack.puzzles.get(self.puzzle.1))
+    }
+
+    fn start_puzzle(&mut self, index: usize) {
+        self.puzzle.1 = index;
+        let game_state = match self.current_puzzle() {
+            Some(puzzle) => puzzle.game_state(),
+            None => return self.enter(GuiState::Packs),
+        };
+        match game_state {
+            Ok(game_state) => {
+                self.start(Mode::Puzzle, game_state);
+                self.enter(GuiState::Game);
+            }
+            Err(e) => eprintln!("failed to start puzzle: {}", e),
+        }
+    }
+
+    fn start_opener(&mut self) {
+        let (index, mirrored) = self.opener;
+        let opener = match self.openers.get(index) {
+            Some(opener) => opener,
+            None => return self.enter(GuiState::Openers),
+        };
+        let mut game_state = new_game(Mode::Opener, &self.settings);
+        match game_state.set_opener(opener, mirrored) {
+            Ok(()) => {
+                self.start(Mode::Opener, game_state);
+                self.enter(GuiState::Game);
+            }
+            Err(e) => eprintln!("failed to start opener: {}", e),
+        }
+    }
+
+    // versus replaces any single player game, and like puzzles isn't saved
+    fn start_versus(&mut self) {
+        self.mode = Mode::Versus;
+        self.in_progress = false;
+        self.versus = Some(Versus::new(self.first_to, &self.settings));
+        self.next_round();
+    }
+
+    fn next_round(&mut self) {
+        let seed = thread_rng().gen();
+        let game_states = (0..VERSUS_PLAYERS)
+            .map(|_| {
+                let mut game_state = new_game(Mode::Versus, &self.settings);
+                game_state.reseed(seed);
+                game_state
+            })
+            .collect();
+        if let Some(versus) = &mut self.versus {
+            versus.next_round(game_states);
+        }
+        self.resume();
+    }
+
+    fn pause(&mut self) {
+        self.controls.reset();
+        self.partner.reset();
+        self.enter(GuiState::Paused);
+    }
+
+    fn resume(&mut self) {
+        self.controls.reset();
+        self.partner.reset();
+        if let Some(versus) = &mut self.versus {
+            versus.reset_controls();
+        }
+        self.countdown = COUNTDOWN_FRAMES;
+        self.enter(GuiState::Countdown);
+    }
+
+    pub fn volume(&self) -> i32 {
+        self.settings.volume()
+    }
+
+    // puzzles and openers are quick to restart, so they aren't kept around between sessions
+    fn save(&self) {
+        let result = if self.in_progress && self.mode != Mode::Puzzle && self.mode != Mode::Opener && self.mode != Mode::Versus {
+            SaveFile::store(Path::new(SAVE_PATH), self.mode, &self.game_state, self.score)
+        } else {
+            SaveFile::remove(Path::new(SAVE_PATH))
+        };
+        if let Err(e) = result {
+            eprintln!("failed to save game: {}", e);
+        }
+    }
+
+    fn leaderboard_entry(&self) -> LeaderboardEntry {
+        LeaderboardEntry {
+            name: self.player_name.clone(),
+            date: Local::now().naive_local().date(),
+            score: self.score,
+            lines: self.game_state.cleared(),
+            time_ms: self.game_state.elapsed().as_millis() as u64,
+            pps: self.game_state.pps(),
+            seed: self.game_state.seed(),
+            splits: self.game_state.splits().to_vec(),
+        }
+    }
+
+    fn record_score(&mut self) {
+        let entry = self.leaderboard_entry();
+        let ranking = match self.mode.ranking() {
+            Some(ranking) => ranking,
+            None => return,
+        };
+        self.leaderboards.insert(&self.mode.key(), self.game_state.ruleset(), ranking, entry);
+        if let Err(e) = self.leaderboards.store(Path::new(LEADERBOARD_PATH)) {
+            eprintln!("failed to save leaderboards: {}", e);
         }
     }
 
     pub fn draw<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>) -> Result<(), String> {
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        let theme = &self.settings.theme;
+        canvas.set_draw_color(theme.background.0);
         canvas.clear();
 
-        self.game_state.draw(canvas)?;
-        self.draw_pause_state(canvas)?;
-        self.draw_scores(canvas, font)?;
+        match self.gui_state {
+            GuiState::Title
+            | GuiState::ModeSelect
+            | GuiState::Replays
+            | GuiState::Packs
+            | GuiState::Puzzles
+            | GuiState::Openers
+            | GuiState::VersusSetup => {
+                self.menu.draw(canvas, font, theme, 250, 150)?;
+                if self.gui_state == GuiState::Replays && self.replays.is_empty() {
+                    draw_text(canvas, font, "No replays yet", theme.text.0, Rect::new(250, 230, 224, 30))?;
+                }
+                if self.gui_state == GuiState::Packs && self.packs.is_empty() {
+                    draw_text(canvas, font, "No puzzle packs found", theme.text.0, Rect::new(250, 230, 336, 30))?;
+                }
+                if self.gui_state == GuiState::Openers && self.openers.is_empty() {
+                    draw_text(canvas, font, "No openers found", theme.text.0, Rect::new(250, 230, 256, 30))?;
+                }
+            }
+            GuiState::Options => self.options_menu.draw(canvas, font, &self.settings)?,
+            GuiState::Controls => self.draw_controls(canvas, font)?,
+            GuiState::HighScores => self.draw_high_scores(canvas, font, 760)?,
+            GuiState::Replay => {
+                if let Some(watching) = &self.watching {
+                    watching.game_state.draw(canvas, &self.settings, watching.finished)?;
+                    self.draw_scores(canvas, font, &watching.game_state, watching.score)?;
+                    self.draw_stats(canvas, font, &watching.game_state)?;
+                    let label = if watching.finished { "Replay finished" } else { "Replay" };
+                    draw_text(canvas, font, label, theme.text.0, Rect::new(450, 30, 16 * label.len() as u32, 40))?;
+                }
+            }
+            GuiState::Game => self.draw_game(canvas, font, self.controls.revealing(), true)?,
+            GuiState::Demo => {
+                if let Some(demo) = &self.demo {
+                    demo.game_state.draw(canvas, &self.settings, false)?;
+                    self.draw_stats(canvas, font, &demo.game_state)?;
+                    let label = "Demo - press any key";
+                    draw_text(canvas, font, label, theme.text.0, Rect::new(450, 30, 16 * label.len() as u32, 40))?;
+                }
+            }
+            GuiState::Versus => {
+                if let Some(versus) = &self.versus {
+                    versus.draw(canvas, font, &self.settings, false)?;
+                }
+            }
+            GuiState::RoundOver => {
+                if let Some(versus) = &self.versus {
+                    versus.draw(canvas, font, &self.settings, true)?;
+                }
+                self.menu.draw(canvas, font, theme, 250, 500)?;
+            }
+            GuiState::Countdown if self.versus.is_some() => {
+                if let Some(versus) = &self.versus {
+                    versus.draw(canvas, font, &self.settings, false)?;
+                }
+                let seconds = self.countdown.div_ceil(60);
+                draw_text(canvas, font, &seconds.to_string(), theme.text.0, Rect::new(370, 550, 60, 100))?;
+            }
+            GuiState::Countdown => {
+                self.draw_game(canvas, font, false, true)?;
+                let seconds = self.countdown.div_ceil(60);
+                canvas.set_draw_color(theme.background.0);
+                canvas.fill_rect(Rect::new(150, 340, 100, 120))?;
+                draw_text(canvas, font, &seconds.to_string(), theme.text.0, Rect::new(170, 350, 60, 100))?;
+            }
+            GuiState::Paused => {
+                // the board and previews stay hidden so the pause can't be used to plan ahead
+                canvas.set_draw_color(theme.grid.0);
+                canvas.draw_rect(Rect::new(0, 0, 401, 800))?;
+                draw_text(canvas, font, "PAUSED", theme.text.0, Rect::new(104, 360, 192, 60))?;
+                self.menu.draw(canvas, font, theme, 450, 320)?;
+            }
+            GuiState::NameEntry => {
+                self.draw_game(canvas, font, true, true)?;
+                draw_text(canvas, font, &format!("New record! Name: {}_", self.player_name), theme.text.0, Rect::new(20, 300, 360, 50))?;
+            }
+            GuiState::Victory => {
+                self.game_state.draw(canvas, &self.settings, true)?;
+                canvas.set_draw_color(theme.background.0);
+                canvas.fill_rect(Rect::new(20, 250, 360, 220))?;
+                let lines = [
+                    "Goal reached!".to_string(),
+                    match self.mode {
+                        Mode::Puzzle => self.current_puzzle().map_or(self.mode.name(), |p| p.name.clone()),
+                        Mode::Opener => self.game_state.opener().map_or(self.mode.name(), |o| o.name().to_string()),
+                        _ => self.mode.name(),
+                    },
+                    if self.mode == Mode::Master {
+                        format!("Grade: {}", master_grade(self.score, self.game_state.level(), self.game_state.section_times()))
+                    } else {
+                        format!("Score: {}", self.score)
+                    },
+                    format!("Time: {}", format_time(self.game_state.elapsed().as_millis() as u64)),
+                ];
+                for (i, line) in lines.iter().enumerate() {
+                    draw_text(canvas, font, line, theme.text.0, Rect::new(40, 270 + 50 * i as i32, 16 * line.len() as u32, 40))?;
+                }
+                self.draw_stats(canvas, font, &self.game_state)?;
+                self.menu.draw(canvas, font, theme, 450, 100)?;
+            }
+            GuiState::Lost if self.mode == Mode::Puzzle => {
+                self.game_state.draw(canvas, &self.settings, true)?;
+                self.draw_scores(canvas, font, &self.game_state, self.score)?;
+                self.menu.draw(canvas, font, theme, 450, 500)?;
+            }
+            GuiState::Lost => {
+                self.draw_game(canvas, font, true, false)?;
+                self.draw_high_scores(canvas, font, 370)?;
+                self.menu.draw(canvas, font, theme, 450, 320)?;
+            }
+        }
 
         canvas.present();
 
         Ok(())
     }
 
-    pub fn draw_pause_state(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
-        match self.gui_state {
-            GuiState::Menu
-            | GuiState::Lost => canvas.set_draw_color(Color::RGB(255, 0, 0)),
-            GuiState::Game => canvas.set_draw_color(Color::RGB(0, 255, 0)),
+    // boards wider than the usual ten columns are drawn at half size, with the hud moved over to clear them
+    fn draw_game<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, reveal: bool, hud: bool) -> Result<(), String> {
+        let extra = self.game_state.board_width() as i32 * 40 - 400;
+        let (scale_x, scale_y) = canvas.scale();
+        if extra > 0 {
+            canvas.set_scale(scale_x / 2.0, scale_y / 2.0)?;
+        }
+        self.game_state.draw(canvas, &self.settings, reveal)?;
+        if hud {
+            if extra > 0 {
+                canvas.set_viewport(Rect::new(extra, 0, 1600, 1600));
+            }
+            self.draw_scores(canvas, font, &self.game_state, self.score)?;
+            self.draw_stats(canvas, font, &self.game_state)?;
+            if let Some(hint) = self.game_state.finesse_hint() {
+                let line = format!("Finesse: {}", hint);
+                draw_text(canvas, font, &line, self.settings.theme.danger.0, Rect::new(450, 300, 10 * line.len() as u32, 22))?;
+            }
+            canvas.set_viewport(None);
+        }
+        canvas.set_scale(scale_x, scale_y)
+    }
+
+    fn draw_scores<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, game_state: &GameState, score: u64) -> Result<(), String> {
+        let text = self.settings.theme.text.0;
+        let best = self.leaderboards.best(&self.mode.key(), game_state.ruleset());
+
+        if self.mode == Mode::Master {
+            let grade = master_grade(score, game_state.level(), game_state.section_times());
+            draw_text(canvas, font, &format!("Score: {}", score), text, Rect::new(450, 330, 200, 40))?;
+            draw_text(canvas, font, &format!("Grade: {}", grade), text, Rect::new(450, 375, 160, 40))?;
+            let line = match game_state.roll_remaining() {
+                Some(remaining) => format!("Credits: {}", format_time(remaining.as_millis() as u64)),
+                None => {
+                    let section = game_state.section_times().len();
+                    let started = game_state.section_times().last().cloned().unwrap_or(0);
+                    let current = game_state.elapsed().as_millis() as u64 - started;
+                    format!("Section {}: {}", section + 1, format_time(current))
+                }
+            };
+            return draw_text(canvas, font, &line, text, Rect::new(450, 420, 12 * line.len() as u32, 24));
+        }
+
+        match self.mode.ranking() {
+            Some(Ranking::Score) => {
+                draw_text(canvas, font, &format!("Score: {}", score), text, Rect::new(450, 330, 200, 40))?;
+                draw_text(canvas, font, &format!("High score: {}", best.map_or(0, |e| e.score)), text, Rect::new(450, 375, 300, 40))?;
+                if game_state.players() > 1 {
+                    let credits: Vec<String> = (0..game_state.players()).map(|i| format!("P{} {}", i + 1, game_state.cleared_by(i))).collect();
+                    let line = format!("Lines: {}", credits.join(" / "));
+                    draw_text(canvas, font, &line, text, Rect::new(450, 420, 12 * line.len() as u32, 24))?;
+                }
+                if let Some(remaining) = game_state.remaining() {
+                    let line = format!("Time left: {}", format_time(remaining.as_millis() as u64));
+                    let color = if remaining.as_secs() < 10 { self.settings.theme.danger.0 } else { text };
+                    draw_text(canvas, font, &line, color, Rect::new(450, 420, 12 * line.len() as u32, 24))?;
+                }
+                Ok(())
+            }
+            Some(Ranking::Time) => {
+                let elapsed = game_state.elapsed().as_millis() as u64;
+                let best_time = best.map_or("-".to_string(), |e| format_time(e.time_ms));
+                draw_text(canvas, font, &format!("Time: {}", format_time(elapsed)), text, Rect::new(450, 330, 240, 40))?;
+                draw_text(canvas, font, &format!("Best: {}", best_time), text, Rect::new(450, 375, 240, 40))?;
+
+                if let Mode::Dig(_) = self.mode {
+                    let line = format!("Garbage left: {}", game_state.garbage_left());
+                    return draw_text(canvas, font, &line, text, Rect::new(450, 420, 12 * line.len() as u32, 24));
+                }
+
+                // compare the latest split against the same split of the personal best
+                let splits = game_state.splits();
+                if let Some(&split) = splits.last() {
+                    let mut line = format!("{} lines: {}", 10 * splits.len(), format_time(split));
+                    if let Some(&pb_split) = best.and_then(|e| e.splits.get(splits.len() - 1)) {
+                        let (sign, delta) = if split <= pb_split { ('-', pb_split - split) } else { ('+', split - pb_split) };
+                        line.push_str(&format!(" ({}{}.{:03})", sign, delta / 1000, delta % 1000));
+                    }
+                    draw_text(canvas, font, &line, text, Rect::new(450, 420, 12 * line.len() as u32, 24))?;
+                }
+                Ok(())
+            }
+            None if self.mode == Mode::Finesse => {
+                let name = self.mode.name();
+                draw_text(canvas, font, &name, text, Rect::new(450, 330, 16 * name.len() as u32, 40))?;
+                let (passed, attempts) = game_state.drill().map_or((0, 0), |d| (d.passed(), d.attempts()));
+                draw_text(canvas, font, &format!("Passed: {}/{}", passed, attempts), text, Rect::new(450, 375, 240, 40))
+            }
+            None if self.mode == Mode::Opener => {
+                let name = game_state.opener().map_or(self.mode.name(), |o| o.name().to_string());
+                draw_text(canvas, font, &name, text, Rect::new(450, 330, 16 * name.len() as u32, 40))?;
+                let (placed, total, deviations, strayed) = game_state.opener()
+                    .map_or((0, 0, 0, false), |o| (o.placed(), o.total(), o.deviations(), !o.strayed().is_empty()));
+                draw_text(canvas, font, &format!("Placed: {}/{}", placed, total), text, Rect::new(450, 375, 240, 40))?;
+                let line = format!("Deviations: {}", deviations);
+                let color = if strayed { self.settings.theme.danger.0 } else { text };
+                draw_text(canvas, font, &line, color, Rect::new(450, 420, 12 * line.len() as u32, 24))
+            }
+            None if self.mode == Mode::Puzzle => {
+                let (name, description) = self.current_puzzle().map_or((String::new(), String::new()), |p| (p.name.clone(), p.description()));
+                draw_text(canvas, font, &name, text, Rect::new(450, 330, 16 * name.len() as u32, 40))?;
+                draw_text(canvas, font, &description, text, Rect::new(450, 375, 12 * description.len() as u32, 30))?;
+                let left = format!("Pieces left: {}", game_state.pieces_left().unwrap_or(0));
+                draw_text(canvas, font, &left, text, Rect::new(450, 420, 12 * left.len() as u32, 24))
+            }
+            // nothing to chase in zen, just the mode and how much has been cleared
+            None => {
+                let name = self.mode.name();
+                draw_text(canvas, font, &name, text, Rect::new(450, 330, 16 * name.len() as u32, 40))?;
+                draw_text(canvas, font, &format!("Lines: {}", game_state.cleared()), text, Rect::new(450, 375, 200, 40))
+            }
         }
-        canvas.fill_rect(Rect::new(750, 0, 50, 50))
     }
 
-    pub fn draw_scores<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>) -> Result<(), String> {
-        canvas.set_draw_color(Color::RGB(0, 255, 0));
+    fn draw_stats<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, game_state: &GameState) -> Result<(), String> {
+        let stats = game_state.stats();
+        let fields = StatField::ALL.iter().filter(|field| self.settings.gameplay.stats.contains(field));
+        for (i, &field) in fields.enumerate() {
+            let value = match field {
+                StatField::Lines => game_state.cleared().to_string(),
+                StatField::Level => game_state.level().to_string(),
+                StatField::Time => format_time(game_state.elapsed().as_millis() as u64),
+                StatField::Pieces => game_state.pieces().to_string(),
+                StatField::Pps => format!("{:.2}", game_state.pps()),
+                StatField::Kpp => format!("{:.2}", game_state.kpp()),
+                StatField::Apm => format!("{:.1}", game_state.apm()),
+                StatField::Clears => format!("{}/{}/{}/{}", stats.clears[0], stats.clears[1], stats.clears[2], stats.clears[3]),
+                StatField::TSpins => stats.t_spins.to_string(),
+                StatField::MaxCombo => stats.max_combo.to_string(),
+                StatField::Finesse => stats.finesse_faults.to_string(),
+            };
+            let line = format!("{}: {}", field.name(), value);
+            draw_text(canvas, font, &line, self.settings.theme.text.0, Rect::new(450, 455 + 27 * i as i32, 12 * line.len() as u32, 24))?;
+        }
+        Ok(())
+    }
+
+    fn draw_high_scores<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, width: u32) -> Result<(), String> {
+        let text = self.settings.theme.text.0;
+        canvas.set_draw_color(self.settings.theme.background.0);
+        canvas.fill_rect(Rect::new(0, 0, width + 30, 800))?;
 
-        let score = font.render(&*format!("Score: {}", self.score))
-            .blended(Color::RGBA(255, 0, 0, 255))
-            .map_err(|e| e.to_string())?;
-        let high_score = font.render(&*format!("High score: {}", self.high_score))
-            .blended(Color::RGBA(255, 0, 0, 255))
-            .map_err(|e| e.to_string())?;
+        let title = format!("High scores: {}", self.board_mode.name());
+        draw_text(canvas, font, &title, text, Rect::new(20, 20, 16 * title.len() as u32, 50))?;
+        let entries = self.leaderboards.entries(&self.board_mode.key(), self.game_state.ruleset());
+        if entries.is_empty() {
+            return draw_text(canvas, font, "No scores yet", text, Rect::new(20, 100, 200, 40));
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let line = format!("{:>2}. {:<12} {:>7} {:>4}L {:>10} {:.2}pps {}",
+                               i + 1, entry.name, entry.score, entry.lines, format_time(entry.time_ms), entry.pps, entry.date);
+            draw_text(canvas, font, &line, text, Rect::new(20, 100 + 40 * i as i32, width, 30))?;
+        }
+        Ok(())
+    }
 
-        let texture_creator = canvas.texture_creator();
+    fn draw_controls<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>) -> Result<(), String> {
+        let text = self.settings.theme.text.0;
+        draw_text(canvas, font, "Controls", text, Rect::new(40, 20, 192, 50))?;
 
-        let score_texture = texture_creator.create_texture_from_surface(score)
-            .map_err(|e| e.to_string())?;
-        let high_score_texture = texture_creator.create_texture_from_surface(high_score)
-            .map_err(|e| e.to_string())?;
+        let mut lines: Vec<String> = Action::ALL.iter()
+            .map(|&action| {
+                let keys: Vec<String> = self.settings.keys.get(action).iter().map(|&k| k.into()).collect();
+                format!("{}: {}", action.name(), keys.join(", "))
+            })
+            .collect();
+        let reveal: Vec<String> = self.settings.keys.reveal.iter().map(|&k| k.into()).collect();
+        lines.push(format!("reveal stack: {}", reveal.join(", ")));
+        let second: Vec<String> = Action::ALL.iter()
+            .filter_map(|&action| self.settings.versus.keys.get(action).first())
+            .map(|&k| k.into())
+            .collect();
+        lines.push(format!("player 2 in versus and co-op: {}", second.join(" ")));
+        lines.push("pause: P, Escape, Start".to_string());
+        lines.push("copy fumen: Ctrl+C".to_string());
+        lines.push("paste fumen: Ctrl+V".to_string());
+        lines.push("menus: arrows or d-pad, Enter or A, Escape or B".to_string());
 
-        canvas.copy(&score_texture, None, Some(Rect::new(450, 400, 200, 100)))?;
-        canvas.copy(&high_score_texture, None, Some(Rect::new(450, 500, 300, 100)))
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(canvas, font, line, text, Rect::new(40, 100 + 50 * i as i32, 15 * line.len() as u32, 30))?;
+        }
+        Ok(())
     }
 
     pub fn handle(&mut self, event: Event) -> bool {
         match event {
-            Event::Quit { .. }
-            | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => false,
-            Event::KeyDown { keycode: Some(Keycode::P), .. } => {
-                match &self.gui_state {
-                    GuiState::Menu => self.gui_state = GuiState::Game,
-                    GuiState::Game => self.gui_state = GuiState::Menu,
-                    GuiState::Lost => {
-                        self.game_state = GameState::new();
-                        self.score = 0;
-                        self.gui_state = GuiState::Game
+            Event::Quit { .. } => {
+                self.save();
+                return false;
+            }
+            Event::Window { win_event: WindowEvent::FocusLost, .. }
+            | Event::Window { win_event: WindowEvent::Minimized, .. } => {
+                if self.gui_state == GuiState::Game || self.gui_state == GuiState::Versus || self.gui_state == GuiState::Countdown {
+                    self.pause();
+                }
+                return true;
+            }
+            // fumen fields are only ten columns wide, so co-op boards can't be copied
+            Event::KeyDown { keycode: Some(Keycode::C), keymod, .. }
+                if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && self.versus.is_none() && self.game_state.players() == 1 => {
+                let fumen = self.game_state.to_fumen(&format!("Score: {}", self.score));
+                if let Err(e) = self.clipboard.set_clipboard_text(&fumen) {
+                    eprintln!("failed to copy fumen: {}", e);
+                }
+                return true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::V), keymod, .. } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) && self.versus.is_none() => {
+                match self.clipboard.clipboard_text().and_then(|text| GameState::from_fumen(&text)) {
+                    Ok(mut game_state) => {
+                        game_state.set_speed(PASTE_MODE.speed());
+                        game_state.set_fade(self.settings.gameplay.fade_frames());
+                        self.start(PASTE_MODE, game_state);
+                        self.pause();
                     }
+                    Err(e) => eprintln!("failed to paste fumen: {}", e),
+                }
+                return true;
+            }
+            _ => (),
+        }
+
+        self.idle = 0;
+        match self.gui_state {
+            GuiState::NameEntry => self.handle_name_entry(event),
+            GuiState::Options => self.handle_options(event),
+            GuiState::Game => self.handle_game(event),
+            GuiState::Versus => self.handle_versus(event),
+            GuiState::Countdown => {
+                match event {
+                    Event::KeyDown { keycode: Some(keycode), .. } if PAUSE_KEYS.contains(&keycode) => self.pause(),
+                    Event::ControllerButtonDown { button: Button::Start, .. } => self.pause(),
+                    _ => (),
                 }
                 true
             }
-            Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                match &self.gui_state {
-                    GuiState::Menu => self.gui_state = GuiState::Game,
-                    GuiState::Game => return self.game_state.handle(event),
-                    GuiState::Lost => {
-                        self.game_state = GameState::new();
-                        self.score = 0;
-                        self.gui_state = GuiState::Game
-                    }
-                };
+            GuiState::Controls
+            | GuiState::HighScores => {
+                let ranked = Mode::ranked();
+                let count = ranked.len();
+                let current = ranked.iter().position(|&m| m.key() == self.board_mode.key()).unwrap_or(0);
+                match MenuInput::from_event(&event) {
+                    Some(MenuInput::Back) | Some(MenuInput::Select) => self.enter(self.back_to),
+                    Some(MenuInput::Left) => self.board_mode = ranked[(current + count - 1) % count],
+                    Some(MenuInput::Right) => self.board_mode = ranked[(current + 1) % count],
+                    _ => (),
+                }
+                true
+            }
+            GuiState::Demo => {
+                if let Event::KeyDown { .. } | Event::ControllerButtonDown { .. } | Event::MouseButtonDown { .. } = event {
+                    self.demo = None;
+                    self.enter(GuiState::Title);
+                }
                 true
             }
+            GuiState::Replay => {
+                if let Some(MenuInput::Back) | Some(MenuInput::Select) = MenuInput::from_event(&event) {
+                    self.watching = None;
+                    self.enter(GuiState::Replays);
+                }
+                true
+            }
+            GuiState::Title
+            | GuiState::ModeSelect
+            | GuiState::Replays
+            | GuiState::Packs
+            | GuiState::Puzzles
+            | GuiState::Openers
+            | GuiState::VersusSetup
+            | GuiState::RoundOver
+            | GuiState::Paused
+            | GuiState::Victory
+            | GuiState::Lost => {
+                if let (GuiState::Paused, Event::KeyDown { keycode: Some(Keycode::P), .. }) = (self.gui_state, &event) {
+                    self.resume();
+                    return true;
+                }
+                match MenuInput::from_event(&event) {
+                    Some(MenuInput::Back) => {
+                        match self.gui_state {
+                            GuiState::Puzzles => self.enter(GuiState::Packs),
+                            GuiState::ModeSelect
+                            | GuiState::Replays
+                            | GuiState::Packs
+                            | GuiState::Openers
+                            | GuiState::VersusSetup
+                            | GuiState::Victory
+                            | GuiState::Lost => self.enter(GuiState::Title),
+                            GuiState::RoundOver => {
+                                self.versus = None;
+                                self.enter(GuiState::Title);
+                            }
+                            GuiState::Paused => self.resume(),
+                            _ => (),
+                        }
+                        true
+                    }
+                    Some(input) if self.gui_state == GuiState::ModeSelect && (input == MenuInput::Left || input == MenuInput::Right) => {
+                        self.start_level = if input == MenuInput::Left {
+                            (self.start_level - 1).max(1)
+                        } else {
+                            (self.start_level + 1).min(MAX_START_LEVEL)
+                        };
+                        let selected = self.menu.selected();
+                        self.enter(GuiState::ModeSelect);
+                        self.menu.select(selected);
+                        true
+                    }
+                    Some(input) if self.gui_state == GuiState::VersusSetup && (input == MenuInput::Left || input == MenuInput::Right) => {
+                        self.first_to = if input == MenuInput::Left {
+                            (self.first_to - 1).max(1)
+                        } else {
+                            (self.first_to + 1).min(MAX_FIRST_TO)
+                        };
+                        self.enter(GuiState::VersusSetup);
+                        true
+                    }
+                    Some(input) => match self.menu.handle(input) {
+                        Some(action) => self.perform(action),
+                        None => true,
+                    },
+                    None => true,
+                }
+            }
+        }
+    }
+
+    fn handle_game(&mut self, event: Event) -> bool {
+        match event {
+            Event::KeyDown { keycode: Some(keycode), repeat, .. } => {
+                if let Some(action) = self.settings.keys.action(keycode) {
+                    // the bot has the first seat to itself
+                    if !repeat && self.bot.is_none() {
+                        let action = self.controls.press(action);
+                        self.game_state.press(action);
+                        self.recorder.record(ReplayInput::Press(action));
+                    }
+                } else if let Some(action) = self.partner_action(keycode) {
+                    if !repeat {
+                        let action = self.partner.press(action);
+                        self.game_state.press_as(1, action);
+                        self.recorder.record(ReplayInput::PartnerPress(action));
+                    }
+                } else if self.settings.keys.reveals(keycode) {
+                    self.controls.reveal(true);
+                } else if PAUSE_KEYS.contains(&keycode) {
+                    self.pause();
+                }
+            }
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                if let Some(action) = self.settings.keys.action(keycode) {
+                    self.controls.release(action);
+                } else if let Some(action) = self.partner_action(keycode) {
+                    self.partner.release(action);
+                } else if self.settings.keys.reveals(keycode) {
+                    self.controls.reveal(false);
+                }
+            }
+            Event::ControllerButtonDown { button: Button::Start, .. } => self.pause(),
             _ => {
-                if self.gui_state == GuiState::Game {
-                    self.game_state.handle(event)
-                } else {
-                    true
+                if let Some((x, y, filled)) = self.game_state.edit(&event) {
+                    self.recorder.record(ReplayInput::Edit { x, y, filled });
+                }
+            }
+        }
+        true
+    }
+
+    // the second player in co-op uses the same keys as in versus
+    fn partner_action(&self, keycode: Keycode) -> Option<Action> {
+        if self.game_state.players() > 1 {
+            self.settings.versus.keys.action(keycode)
+        } else {
+            None
+        }
+    }
+
+    fn handle_versus(&mut self, event: Event) -> bool {
+        let handled = match &mut self.versus {
+            Some(versus) => versus.handle(&event, &self.settings),
+            None => false,
+        };
+        match event {
+            Event::KeyDown { keycode: Some(keycode), .. } if !handled && PAUSE_KEYS.contains(&keycode) => self.pause(),
+            Event::ControllerButtonDown { button: Button::Start, .. } if !handled => self.pause(),
+            _ => (),
+        }
+        true
+    }
+
+    fn handle_options(&mut self, event: Event) -> bool {
+        match self.options_menu.handle(&event, &mut self.settings) {
+            OptionsOutcome::Unchanged => (),
+            OptionsOutcome::Changed => {
+                if let Err(e) = self.settings.store(Path::new(SETTINGS_PATH)) {
+                    eprintln!("failed to save settings: {}", e);
                 }
             }
+            OptionsOutcome::Closed => self.enter(self.back_to),
+        }
+        true
+    }
+
+    fn handle_name_entry(&mut self, event: Event) -> bool {
+        match event {
+            Event::TextInput { text, .. } => {
+                for c in text.chars().filter(|c| !c.is_control()) {
+                    if self.player_name.chars().count() < MAX_NAME_LENGTH {
+                        self.player_name.push(c);
+                    }
+                }
+            }
+            Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                self.player_name.pop();
+            }
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } if !self.player_name.trim().is_empty() => {
+                self.record_score();
+                self.show_results();
+            }
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => self.show_results(),
+            _ => (),
         }
+        true
     }
 
     pub fn update(&mut self, audio: SyncSender<Sound>) {
         match self.gui_state {
-            GuiState::Menu
-            | GuiState::Lost => (),
-            GuiState::Game => {
-                match self.game_state.update(audio) {
-                    Some(s) => {
-                        self.score += s;
+            GuiState::Game => self.update_game(audio),
+            GuiState::Versus => {
+                let over = match &mut self.versus {
+                    Some(versus) => versus.update(&audio, &self.settings),
+                    None => false,
+                };
+                if over {
+                    self.enter(GuiState::RoundOver);
+                }
+            }
+            GuiState::Countdown => {
+                self.countdown = self.countdown.saturating_sub(1);
+                if self.countdown == 0 {
+                    self.enter(if self.versus.is_some() { GuiState::Versus } else { GuiState::Game });
+                }
+            }
+            GuiState::Title => {
+                self.idle += 1;
+                if self.idle >= self.settings.bot.demo_seconds as u64 * 60 {
+                    self.demo = Some(Demo {
+                        game_state: new_game(Mode::ALL[1], &self.settings),
+                        bot: Bot::new(&self.settings),
+                    });
+                    self.enter(GuiState::Demo);
+                }
+            }
+            GuiState::Demo => {
+                let over = match &mut self.demo {
+                    Some(demo) => {
+                        for action in demo.bot.tick(&demo.game_state) {
+                            demo.game_state.press(action);
+                        }
+                        demo.game_state.update(audio, 1).is_none()
                     }
-                    None => {
-                        self.gui_state = GuiState::Lost;
-                        if self.score > self.high_score {
-                            self.high_score = self.score;
+                    None => true,
+                };
+                if over {
+                    self.demo = None;
+                    self.enter(GuiState::Title);
+                }
+            }
+            GuiState::Replay => {
+                if let Some(watching) = &mut self.watching {
+                    if !watching.finished {
+                        match watching.player.tick(&mut watching.game_state, audio) {
+                            Some(s) => watching.score += s,
+                            None => watching.finished = true,
                         }
+                        watching.finished |= watching.player.finished();
                     }
                 }
             }
+            _ => (),
+        }
+    }
+
+    fn update_game(&mut self, audio: SyncSender<Sound>) {
+        if let Some(bot) = &mut self.bot {
+            for action in bot.tick(&self.game_state) {
+                self.game_state.press(action);
+                self.recorder.record(ReplayInput::Press(action));
+            }
+        }
+
+        for action in self.controls.tick(&self.settings.handling, self.game_state.board_width()) {
+            self.game_state.apply(action);
+            self.recorder.record(ReplayInput::Action(action));
+        }
+
+        for action in self.partner.tick(&self.settings.handling, self.game_state.board_width()) {
+            self.game_state.apply_as(1, action);
+            self.recorder.record(ReplayInput::PartnerAction(action));
+        }
+
+        let soft_drop_factor = if self.controls.soft_dropping() { self.settings.handling.sdf as u64 } else { 1 };
+        let partner_factor = if self.partner.soft_dropping() { self.settings.handling.sdf as u64 } else { 1 };
+        self.recorder.record(ReplayInput::SoftDropFactor(soft_drop_factor));
+        self.recorder.record(ReplayInput::PartnerSoftDropFactor(partner_factor));
+        let result = self.game_state.update_seats(audio, &[soft_drop_factor, partner_factor]);
+        self.recorder.advance();
+
+        match result {
+            Some(s) => {
+                self.score += s;
+                if self.game_state.finished() {
+                    self.end_game();
+                } else if self.game_state.pieces() >= self.autosaved_at + AUTOSAVE_INTERVAL {
+                    self.autosaved_at = self.game_state.pieces();
+                    self.save();
+                }
+            }
+            None => self.end_game(),
+        }
+    }
+
+    fn end_game(&mut self) {
+        self.in_progress = false;
+        let recorder = std::mem::replace(&mut self.recorder, ReplayRecorder::new(&self.game_state));
+        if let Err(e) = recorder.finish(self.mode, self.score).store(Path::new(REPLAY_DIR)) {
+            eprintln!("failed to save replay: {}", e);
+        }
+
+        if self.mode == Mode::Puzzle && self.game_state.finished() {
+            if let Some(pack) = self.packs.get(self.puzzle.0) {
+                self.progress.mark(&pack.name, &pack.puzzles[self.puzzle.1].name);
+                if let Err(e) = self.progress.store(Path::new(PROGRESS_PATH)) {
+                    eprintln!("failed to save puzzle progress: {}", e);
+                }
+            }
+        }
+
+        // timed modes only rank runs that reached the goal, and games the bot played don't rank at all
+        let qualifies = match self.mode.ranking() {
+            Some(_) if self.bot.is_some() => false,
+            Some(ranking) if ranking == Ranking::Score || self.game_state.finished() => {
+                self.leaderboards.qualifies(&self.mode.key(), self.game_state.ruleset(), ranking, &self.leaderboard_entry())
+            }
+            _ => false,
+        };
+        if qualifies {
+            self.enter(GuiState::NameEntry);
+        } else {
+            self.show_results();
+        }
+        self.save();
+    }
+
+    fn show_results(&mut self) {
+        if self.game_state.finished() {
+            self.enter(GuiState::Victory);
+        } else {
+            self.enter(GuiState::Lost);
         }
     }
-}
\ No newline at end of file
+}
+
+fn new_game(mode: Mode, settings: &Settings) -> GameState {
+    // openers are drawn with the standard pieces, whatever set is picked in the options
+    let pieces = if mode == Mode::Opener {
+        PieceSet::standard()
+    } else {
+        PieceSet::load(Path::new(&settings.gameplay.piece_set)).unwrap_or_else(|e| {
+            eprintln!("failed to load piece set, using the standard pieces: {}", e);
+            PieceSet::standard()
+        })
+    };
+    let mut game_state = GameState::new(mode.goal(), mode.speed(), mode.start_level(), &pieces);
+    let garbage = &settings.garbage;
+    let seed = game_state.seed();
+    match mode {
+        Mode::Dig(rows) => game_state.add_garbage(GarbageGenerator::new(garbage.holes, garbage.messiness, garbage.pattern, seed), rows),
+        Mode::Versus => game_state.add_garbage(GarbageGenerator::new(garbage.holes, garbage.messiness, garbage.pattern, seed), 0),
+        _ => (),
+    }
+    game_state.set_attack(garbage.attack.clone(), garbage.delay);
+    match mode {
+        Mode::Big => game_state.set_scale(2),
+        Mode::Coop => game_state.set_players(COOP_PLAYERS, COOP_WIDTH),
+        Mode::Finesse => game_state.set_trainer(),
+        _ => (),
+    }
+    game_state.set_fade(settings.gameplay.fade_frames());
+    game_state
+}
+
+fn format_time(ms: u64) -> String {
+    format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Cell {
+    Empty,
+    Block,
+    Garbage,
+}
+
+// cells are stored row by row, with y = 0 at the top of the board;
+// placed holds the frame each cell was filled on, so blocks can fade with age
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Board {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    placed: Vec<i64>,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Board {
+        Board {
+            width,
+            height,
+            cells: vec![Cell::Empty; width * height],
+            placed: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Cell {
+        self.cells[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        self.place(x, y, cell, 0);
+    }
+
+    pub fn place(&mut self, x: usize, y: usize, cell: Cell, frame: i64) {
+        self.cells[y * self.width + x] = cell;
+        self.placed[y * self.width + x] = frame;
+    }
+
+    pub fn placed_at(&self, x: usize, y: usize) -> i64 {
+        self.placed[y * self.width + x]
+    }
+
+    pub fn is_free(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+            && self.get(x as usize, y as usize) == Cell::Empty
+    }
+
+    fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn occupied_above(&self, rows: usize) -> bool {
+        (0..rows.min(self.height)).any(|y| self.row(y).iter().any(|&c| c != Cell::Empty))
+    }
+
+    pub fn garbage_rows(&self) -> usize {
+        (0..self.height).filter(|&y| self.row(y).contains(&Cell::Garbage)).count()
+    }
+
+    // the rows with no gaps left, from the top down
+    pub fn full_rows(&self) -> Vec<usize> {
+        (0..self.height)
+            .filter(|&y| self.row(y).iter().all(|&c| c != Cell::Empty))
+            .collect()
+    }
+
+    // removes every full row and drops the rows above into place, returning how many went
+    pub fn clear_lines(&mut self) -> usize {
+        let width = self.width;
+        let full = self.full_rows();
+        for &y in full.iter() {
+            self.cells.drain(y * width..(y + 1) * width);
+            self.cells.splice(0..0, vec![Cell::Empty; width]);
+            self.placed.drain(y * width..(y + 1) * width);
+            self.placed.splice(0..0, vec![0; width]);
+        }
+        full.len()
+    }
+
+    // throws away the bottom row and moves everything above it down one
+    pub fn remove_bottom_row(&mut self) {
+        let len = self.cells.len();
+        self.cells.truncate(len - self.width);
+        self.cells.splice(0..0, vec![Cell::Empty; self.width]);
+        self.placed.truncate(len - self.width);
+        self.placed.splice(0..0, vec![0; self.width]);
+    }
+
+    // pushes a row in from the bottom; returns false if that shoved blocks off the top
+    pub fn push_row(&mut self, row: Vec<Cell>) -> bool {
+        let overflow = self.row(0).iter().any(|&c| c != Cell::Empty);
+        self.cells.drain(0..self.width);
+        self.cells.extend(row.into_iter().chain(std::iter::repeat(Cell::Empty)).take(self.width));
+        self.placed.drain(0..self.width);
+        self.placed.extend(std::iter::repeat_n(0, self.width));
+        !overflow
+    }
+}
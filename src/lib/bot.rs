@@ -0,0 +1,430 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Action, Board, BotSettings, Cell, ExternalBot, GameState, PlayerPiece, Settings};
+use crate::lib::{finesse, search};
+
+// each feature of the board after a placement is multiplied by its weight and the placement with the
+// highest total wins, so penalties want negative weights; t_slots counts spots a t piece could spin into
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotWeights {
+    pub height: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub wells: f64,
+    pub row_transitions: f64,
+    pub column_transitions: f64,
+    pub t_slots: f64,
+    pub lines: f64,
+}
+
+// everything the bot needs to know to pick a placement; a hold swaps the piece for the first one in the queue
+#[derive(Clone)]
+pub struct Position {
+    pub board: Board,
+    pub piece: PlayerPiece,
+    pub queue: Vec<PlayerPiece>,
+    pub hold: bool,
+    pub combo: u64,
+    pub back_to_back: bool,
+}
+
+// where to put the piece, as the cells it should end up in, and the way there if the planner found one;
+// a plan without any cells just drops the piece
+pub struct Plan {
+    pub hold: bool,
+    pub target: Vec<(i32, i32)>,
+    pub spun: bool,
+    pub path: Vec<Action>,
+}
+
+enum Brain {
+    Heuristic {
+        requests: Sender<(u64, Position)>,
+        plans: Receiver<(u64, Plan)>,
+    },
+    External(Box<ExternalBot>),
+}
+
+// a piece knocked off its path, with the plan it should still end up following
+struct Route {
+    turn: u64,
+    piece: PlayerPiece,
+    board: Board,
+    target: Vec<(i32, i32)>,
+    spun: bool,
+}
+
+// the thinking happens on a worker thread or in another program; every frame the bot checks for a
+// finished plan and walks the piece along its path. when gravity knocks the piece off it a new path is
+// searched for on a worker too, and the old one followed until it comes back. the bot always plays the
+// first seat, so in co-op the second player is a person
+pub struct Bot {
+    brain: Brain,
+    settings: BotSettings,
+    routes: Sender<Route>,
+    routed: Receiver<(Route, Vec<Action>)>,
+    rerouting: bool,
+    asked: Option<u64>,
+    plan: Option<Plan>,
+    previews: usize,
+    delay: u32,
+    wait: u32,
+}
+
+impl Default for BotWeights {
+    fn default() -> Self {
+        BotWeights {
+            height: -0.51,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.1,
+            row_transitions: -0.05,
+            column_transitions: -0.1,
+            t_slots: 0.2,
+            lines: 0.76,
+        }
+    }
+}
+
+impl Bot {
+    // an external bot that fails to start, or is given pieces it has no name for, leaves the built in one
+    // playing instead
+    pub fn new(settings: &Settings) -> Bot {
+        let external = match settings.bot.command.first() {
+            Some(program) => ExternalBot::spawn(&settings.bot.command)
+                .map_err(|e| eprintln!("couldn't start the bot {}: {}", program, e))
+                .ok(),
+            None => None,
+        };
+        // an external bot sees what a player would; the built in one gets one more piece than its
+        // lookahead, for the one a hold would bring in
+        let (brain, previews) = match external {
+            Some(external) => (Brain::External(Box::new(external)), settings.gameplay.preview_count.max(1)),
+            None => (heuristic(&settings.bot), settings.bot.lookahead + 1),
+        };
+        let (routes, routed) = router();
+        Bot {
+            brain,
+            settings: settings.bot.clone(),
+            routes,
+            routed,
+            rerouting: false,
+            asked: None,
+            plan: None,
+            previews,
+            delay: settings.bot.delay,
+            wait: 0,
+        }
+    }
+
+    // the inputs to press this frame for the first seat of the game
+    pub fn tick(&mut self, game_state: &GameState) -> Vec<Action> {
+        let position = match game_state.position(0, self.previews) {
+            Some(position) => position,
+            None => return vec![],
+        };
+        if let Brain::External(_) = self.brain {
+            if !ExternalBot::can_play(&position) {
+                eprintln!("the bot only knows the seven tetrominoes, so the built in one is playing instead");
+                self.brain = heuristic(&self.settings);
+                self.previews = self.settings.lookahead + 1;
+                self.asked = None;
+                return vec![];
+            }
+        }
+        let turn = game_state.pieces();
+        if self.asked != Some(turn) {
+            self.asked = Some(turn);
+            self.plan = None;
+            self.rerouting = false;
+            match &mut self.brain {
+                Brain::Heuristic { requests, .. } => {
+                    let _ = requests.send((turn, position.clone()));
+                }
+                Brain::External(external) => external.request(turn, &position),
+            }
+        }
+        let plans = match &mut self.brain {
+            Brain::Heuristic { plans, .. } => plans.try_iter().collect(),
+            Brain::External(external) => external.plans(),
+        };
+        for (planned, plan) in plans {
+            if planned == turn {
+                self.plan = Some(plan);
+            }
+        }
+
+        let plan = match &mut self.plan {
+            Some(plan) => plan,
+            None => return vec![],
+        };
+        if self.wait > 0 {
+            self.wait -= 1;
+            return vec![];
+        }
+        self.wait = self.delay;
+        if plan.hold {
+            plan.hold = false;
+            return vec![Action::Hold];
+        }
+
+        // a new path is only any use if the piece hasn't moved since it was asked for
+        let mut fresh = false;
+        for (route, path) in self.routed.try_iter() {
+            if route.turn == turn {
+                self.rerouting = false;
+                if cells(&route.piece) == cells(&position.piece) {
+                    plan.path = path;
+                    fresh = true;
+                }
+            }
+        }
+        if !fresh && !self.rerouting && !leads_to(&position.piece, &position.board, &plan.path, &plan.target) {
+            self.rerouting = true;
+            let _ = self.routes.send(Route {
+                turn,
+                piece: position.piece.clone(),
+                board: position.board.clone(),
+                target: plan.target.clone(),
+                spun: plan.spun,
+            });
+        }
+        // a plan that came without a path waits for one
+        if plan.path.is_empty() {
+            return vec![];
+        }
+        let path = if self.delay == 0 { std::mem::take(&mut plan.path) } else { vec![plan.path.remove(0)] };
+        if plan.path.is_empty() {
+            self.plan = None;
+        }
+        path
+    }
+}
+
+fn heuristic(settings: &BotSettings) -> Brain {
+    let (requests, incoming) = channel::<(u64, Position)>();
+    let (outgoing, plans) = channel();
+    let weights = settings.weights.clone();
+    let lookahead = settings.lookahead;
+    thread::spawn(move || {
+        for (turn, position) in incoming {
+            if let Some(plan) = think(&position, &weights, lookahead) {
+                if outgoing.send((turn, plan)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Brain::Heuristic { requests, plans }
+}
+
+// the shortest way to the target, preferring one that spins in if the plan did; a target the piece
+// can't reach gets a straight drop
+fn router() -> (Sender<Route>, Receiver<(Route, Vec<Action>)>) {
+    let (routes, incoming) = channel::<Route>();
+    let (outgoing, routed) = channel();
+    thread::spawn(move || {
+        for route in incoming {
+            let path = search::reachable(&route.piece, &route.board).into_iter()
+                .filter(|placement| cells(&placement.piece) == route.target)
+                .min_by_key(|placement| (placement.spun != route.spun, placement.path.len()))
+                .map_or(vec![Action::HardDrop], |placement| placement.path);
+            if outgoing.send((route, path)).is_err() {
+                break;
+            }
+        }
+    });
+    (routes, routed)
+}
+
+// the piece in play (or the one a hold would bring in) is tried in every spot it can reach, tucks and
+// spins included; the pieces after it only get straight drops, which keeps the lookahead quick
+fn think(position: &Position, weights: &BotWeights, lookahead: usize) -> Option<Plan> {
+    let mut options = vec![(false, position.piece.clone(), position.queue.clone())];
+    if position.hold && !position.queue.is_empty() {
+        let mut queue = position.queue.clone();
+        let swapped = std::mem::replace(&mut queue[0], position.piece.respawned());
+        options.push((true, swapped, queue));
+    }
+
+    let mut best: Option<(f64, Plan)> = None;
+    for (hold, piece, queue) in options {
+        for placement in search::reachable(&piece, &position.board) {
+            let (board, lines) = lock(&position.board, &placement.piece);
+            let score = weights.lines * lines as f64 + look(&board, &queue[..lookahead.min(queue.len())], weights);
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, Plan { hold, target: cells(&placement.piece), spun: placement.spun, path: placement.path }));
+            }
+        }
+    }
+    best.map(|(_, plan)| plan)
+}
+
+// the best score the queue can reach from this board, dropping each piece straight down
+fn look(board: &Board, queue: &[PlayerPiece], weights: &BotWeights) -> f64 {
+    let piece = match queue.first() {
+        Some(piece) => piece,
+        None => return evaluate(board, weights),
+    };
+    finesse::placements(piece, board).iter()
+        .map(|(placed, _)| {
+            let (board, lines) = lock(board, placed);
+            weights.lines * lines as f64 + look(&board, &queue[1..], weights)
+        })
+        .fold(None, |best: Option<f64>, score| Some(best.map_or(score, |b| b.max(score))))
+        .unwrap_or_else(|| evaluate(board, weights))
+}
+
+// whether following the path from where the piece is now still ends with it on the target
+fn leads_to(piece: &PlayerPiece, board: &Board, path: &[Action], target: &[(i32, i32)]) -> bool {
+    let mut piece = piece.clone();
+    for action in path {
+        let moved = match action {
+            Action::MoveLeft => piece.go_left(board),
+            Action::MoveRight => piece.go_right(board),
+            Action::SoftDrop => piece.go_down(board),
+            Action::HardDrop => Some(piece.lowest_possible_position(board)),
+            Action::Rotate => piece.rotate(board),
+            Action::Hold => None,
+        };
+        piece = match moved {
+            Some(moved) => moved,
+            None => return false,
+        };
+    }
+    path.last() == Some(&Action::HardDrop) && cells(&piece) == target
+}
+
+fn lock(board: &Board, piece: &PlayerPiece) -> (Board, usize) {
+    let mut board = board.clone();
+    for p in piece.get_tiles() {
+        board.set(p.x() as usize, p.y() as usize, Cell::Block);
+    }
+    let lines = board.clear_lines() / piece.scale() as usize;
+    (board, lines)
+}
+
+fn evaluate(board: &Board, weights: &BotWeights) -> f64 {
+    let (width, height) = (board.width() as i32, board.height() as i32);
+    let filled = |x: i32, y: i32| !board.is_free(x, y);
+    let heights: Vec<i32> = (0..width)
+        .map(|x| (0..height).find(|&y| filled(x, y)).map_or(0, |y| height - y))
+        .collect();
+
+    let aggregate: i32 = heights.iter().sum();
+    let holes = (0..width)
+        .map(|x| (height - heights[x as usize]..height).filter(|&y| !filled(x, y)).count() as i32)
+        .sum::<i32>();
+    let bumpiness: i32 = heights.windows(2).map(|pair| (pair[0] - pair[1]).abs()).sum();
+    let wells: i32 = (0..width as usize)
+        .map(|x| {
+            let left = if x == 0 { height } else { heights[x - 1] };
+            let right = if x + 1 == width as usize { height } else { heights[x + 1] };
+            (left.min(right) - heights[x]).max(0)
+        })
+        .sum();
+    let top = height - heights.iter().cloned().max().unwrap_or(0);
+    let row_transitions = (top..height)
+        .map(|y| (-1..width).filter(|&x| filled(x, y) != filled(x + 1, y)).count() as i32)
+        .sum::<i32>();
+    let column_transitions = (0..width)
+        .map(|x| (top..height).filter(|&y| filled(x, y) != filled(x, y + 1)).count() as i32)
+        .sum::<i32>();
+
+    // a downward pointing t fits, can't fall any further, and has three of its four corners blocked
+    let t_slots = (0..width)
+        .flat_map(|x| (top.max(1)..height).map(move |y| (x, y)))
+        .filter(|&(x, y)| {
+            [(x - 1, y), (x, y), (x + 1, y), (x, y + 1)].iter().all(|&(x, y)| board.is_free(x, y))
+                && filled(x, y + 2)
+                && [(x - 1, y - 1), (x + 1, y - 1), (x - 1, y + 1), (x + 1, y + 1)].iter().filter(|&&(x, y)| filled(x, y)).count() >= 3
+        })
+        .count() as i32;
+
+    weights.height * aggregate as f64
+        + weights.holes * holes as f64
+        + weights.bumpiness * bumpiness as f64
+        + weights.wells * wells as f64
+        + weights.row_transitions * row_transitions as f64
+        + weights.column_transitions * column_transitions as f64
+        + weights.t_slots * t_slots as f64
+}
+
+fn cells(piece: &PlayerPiece) -> Vec<(i32, i32)> {
+    let mut cells: Vec<(i32, i32)> = piece.get_tiles().iter().map(|p| (p.x(), p.y())).collect();
+    cells.sort();
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::lib::{Goal, PieceSet, Speed};
+
+    fn position(piece: char, next: char) -> Position {
+        let pieces = PieceSet::standard();
+        Position {
+            board: Board::new(10, 20),
+            piece: pieces.find(piece).unwrap(),
+            queue: vec![pieces.find(next).unwrap()],
+            hold: true,
+            combo: 0,
+            back_to_back: false,
+        }
+    }
+
+    #[test]
+    fn plans_come_with_a_path_to_their_target() {
+        for &(piece, next) in [('I', 'O'), ('S', 'Z'), ('T', 'L')].iter() {
+            let position = position(piece, next);
+            let plan = think(&position, &BotWeights::default(), 1).unwrap();
+            let start = if plan.hold { &position.queue[0] } else { &position.piece };
+            assert!(leads_to(start, &position.board, &plan.path, &plan.target));
+        }
+    }
+
+    #[test]
+    fn a_piece_knocked_off_its_path_needs_a_new_one() {
+        let position = position('O', 'O');
+        let plan = think(&position, &BotWeights::default(), 0).unwrap();
+        assert!(!plan.hold);
+        let shifted = position.piece.go_left(&position.board).or_else(|| position.piece.go_right(&position.board)).unwrap();
+        assert!(!leads_to(&shifted, &position.board, &plan.path, &plan.target));
+        assert!(!leads_to(&position.piece, &position.board, &[], &plan.target));
+    }
+
+    fn play_out(speed: Speed, level: u64) {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut settings = Settings::default();
+        settings.bot.delay = 2;
+        let mut bot = Bot::new(&settings);
+        let mut game_state = GameState::new(Goal::None, speed, level, &PieceSet::standard());
+        let started = Instant::now();
+        while game_state.pieces() < 5 && started.elapsed() < Duration::from_secs(10) {
+            for action in bot.tick(&game_state) {
+                game_state.press(action);
+            }
+            assert!(game_state.update(audio.clone(), 1).is_some());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(game_state.pieces(), 5);
+    }
+
+    #[test]
+    fn the_bot_plays_pieces_out() {
+        play_out(Speed::Guideline, 1);
+    }
+
+    #[test]
+    fn the_bot_plays_pieces_out_at_20g() {
+        // every piece lands before the bot's first move, so each one needs a path found from the floor
+        play_out(Speed::Master, 500);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashSet, VecDeque};
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Board, PlayerPiece};
+use crate::lib::search::{self, Placement};
+
+// a das input holds the key until the piece hits something, and still only counts as one press
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum FinesseInput {
+    Left,
+    Right,
+    DasLeft,
+    DasRight,
+    Rotate,
+}
+
+// one piece at a time on an empty board, with a target to hit; a miss or a wasted key means the same piece again
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Drill {
+    target: Option<PlayerPiece>,
+    attempts: u64,
+    passed: u64,
+    rng: Pcg32,
+}
+
+// the searches made for the last piece and board seen, which a piece usually locks on again: drills aim
+// at a target on the board the piece then locks on, and every retry starts from the same empty board
+#[derive(Clone, Default)]
+pub struct FinesseCache {
+    spawned: Vec<(i32, i32)>,
+    filled: Vec<bool>,
+    placements: Vec<(PlayerPiece, Vec<FinesseInput>)>,
+    reachable: Option<Vec<Placement>>,
+}
+
+impl FinesseInput {
+    pub const ALL: [FinesseInput; 5] = [
+        FinesseInput::Left,
+        FinesseInput::Right,
+        FinesseInput::DasLeft,
+        FinesseInput::DasRight,
+        FinesseInput::Rotate,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FinesseInput::Left => "left",
+            FinesseInput::Right => "right",
+            FinesseInput::DasLeft => "DAS left",
+            FinesseInput::DasRight => "DAS right",
+            FinesseInput::Rotate => "rotate",
+        }
+    }
+
+    pub fn apply(self, piece: &PlayerPiece, board: &Board) -> Option<PlayerPiece> {
+        match self {
+            FinesseInput::Left => piece.go_left(board),
+            FinesseInput::Right => piece.go_right(board),
+            FinesseInput::DasLeft => {
+                let mut moved = piece.go_left(board)?;
+                while let Some(p) = moved.go_left(board) {
+                    moved = p;
+                }
+                Some(moved)
+            }
+            FinesseInput::DasRight => {
+                let mut moved = piece.go_right(board)?;
+                while let Some(p) = moved.go_right(board) {
+                    moved = p;
+                }
+                Some(moved)
+            }
+            FinesseInput::Rotate => piece.rotate(board),
+        }
+    }
+}
+
+// every spot a hard drop can reach from the piece's spawn, each with the fewest inputs that get there
+pub fn placements(piece: &PlayerPiece, board: &Board) -> Vec<(PlayerPiece, Vec<FinesseInput>)> {
+    let start = piece.respawned();
+    let mut seen = HashSet::new();
+    let mut landed = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut found = vec![];
+    seen.insert(cells(&start));
+    queue.push_back((start, vec![]));
+
+    while let Some((piece, inputs)) = queue.pop_front() {
+        let dropped = piece.lowest_possible_position(board);
+        if landed.insert(cells(&dropped)) {
+            found.push((dropped, inputs.clone()));
+        }
+        for &input in FinesseInput::ALL.iter() {
+            if let Some(next) = input.apply(&piece, board) {
+                if seen.insert(cells(&next)) {
+                    let mut path = inputs.clone();
+                    path.push(input);
+                    queue.push_back((next, path));
+                }
+            }
+        }
+    }
+    found
+}
+
+pub fn describe(inputs: &[FinesseInput]) -> String {
+    let mut names: Vec<&str> = inputs.iter().map(|input| input.name()).collect();
+    names.push("hard drop");
+    names.join(", ")
+}
+
+impl FinesseCache {
+    pub fn placements(&mut self, piece: &PlayerPiece, board: &Board) -> &[(PlayerPiece, Vec<FinesseInput>)] {
+        self.search(piece, board);
+        &self.placements
+    }
+
+    // every spot the piece can lock from its spawn, tucks and spins included
+    pub fn reachable(&mut self, piece: &PlayerPiece, board: &Board) -> &[Placement] {
+        self.search(piece, board);
+        let start = piece.respawned();
+        self.reachable.get_or_insert_with(|| search::reachable(&start, board))
+    }
+
+    // the fewest inputs before the hard drop that put the piece where it landed, if it could get there without soft dropping
+    pub fn optimal_inputs(&mut self, piece: &PlayerPiece, board: &Board) -> Option<Vec<FinesseInput>> {
+        let target = cells(piece);
+        self.placements(piece, board).iter()
+            .find(|(placed, _)| cells(placed) == target)
+            .map(|(_, inputs)| inputs.clone())
+    }
+
+    // the tucks and spins are only searched for when a piece turns out to need them
+    fn search(&mut self, piece: &PlayerPiece, board: &Board) {
+        let spawned = cells(&piece.respawned());
+        let filled: Vec<bool> = (0..board.height() as i32)
+            .flat_map(|y| (0..board.width() as i32).map(move |x| (x, y)))
+            .map(|(x, y)| !board.is_free(x, y))
+            .collect();
+        if spawned != self.spawned || filled != self.filled {
+            self.placements = placements(piece, board);
+            self.reachable = None;
+            self.spawned = spawned;
+            self.filled = filled;
+        }
+    }
+}
+
+impl Drill {
+    pub fn new(seed: u64) -> Drill {
+        Drill {
+            target: None,
+            attempts: 0,
+            passed: 0,
+            rng: Pcg32::seed_from_u64(seed),
+        }
+    }
+
+    pub fn target(&self) -> Option<&PlayerPiece> {
+        self.target.as_ref()
+    }
+
+    pub fn attempts(&self) -> u64 {
+        self.attempts
+    }
+
+    pub fn passed(&self) -> u64 {
+        self.passed
+    }
+
+    // a retry keeps its target, anything else gets a new one
+    pub fn aim(&mut self, piece: &PlayerPiece, board: &Board, cache: &mut FinesseCache) {
+        if self.target.as_ref().is_some_and(|t| t.name() == piece.name()) {
+            return;
+        }
+        let options = cache.placements(piece, board);
+        self.target = if options.is_empty() {
+            None
+        } else {
+            Some(options[self.rng.gen_range(0, options.len())].0.clone())
+        };
+    }
+
+    // true when the piece landed on the target without any wasted keys
+    pub fn check(&mut self, piece: &PlayerPiece, fault: bool) -> bool {
+        self.attempts += 1;
+        let hit = self.target.as_ref().is_some_and(|t| cells(t) == cells(piece));
+        if hit && !fault {
+            self.passed += 1;
+            self.target = None;
+        }
+        hit && !fault
+    }
+}
+
+fn cells(piece: &PlayerPiece) -> Vec<(i32, i32)> {
+    let mut cells: Vec<(i32, i32)> = piece.get_tiles().iter().map(|p| (p.x(), p.y())).collect();
+    cells.sort();
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{Cell, PieceSet};
+
+    fn piece(name: char) -> PlayerPiece {
+        PieceSet::standard().find(name).unwrap()
+    }
+
+    fn columns(piece: &PlayerPiece) -> Vec<i32> {
+        let mut columns: Vec<i32> = piece.get_tiles().iter().map(|t| t.x()).collect();
+        columns.sort();
+        columns.dedup();
+        columns
+    }
+
+    #[test]
+    fn every_column_is_reached_with_the_fewest_inputs() {
+        let board = Board::new(10, 20);
+        let found = placements(&piece('O'), &board);
+        assert_eq!(found.len(), 9);
+        let inputs = |wanted: Vec<i32>| found.iter().find(|(p, _)| columns(p) == wanted).map(|(_, inputs)| inputs.clone()).unwrap();
+        assert_eq!(inputs(vec![0, 1]), vec![FinesseInput::DasLeft]);
+        assert_eq!(inputs(vec![1, 2]), vec![FinesseInput::DasLeft, FinesseInput::Right]);
+        assert_eq!(inputs(vec![8, 9]), vec![FinesseInput::DasRight]);
+    }
+
+    #[test]
+    fn a_straight_drop_needs_no_inputs() {
+        let board = Board::new(10, 20);
+        let o = piece('O');
+        let mut cache = FinesseCache::default();
+        assert_eq!(cache.optimal_inputs(&o.lowest_possible_position(&board), &board), Some(vec![]));
+        assert_eq!(describe(&[FinesseInput::DasLeft, FinesseInput::Rotate]), "DAS left, rotate, hard drop");
+    }
+
+    #[test]
+    fn tucks_are_only_found_by_the_full_search() {
+        // a roof over the left of the board, with room for an o under it
+        let mut board = Board::new(10, 20);
+        for x in 0..4 {
+            board.place(x, 16, Cell::Garbage, 0);
+        }
+        let o = piece('O');
+        let tucked = |p: &PlayerPiece| columns(p) == vec![0, 1] && p.get_tiles().iter().all(|t| t.y() >= 18);
+        let mut cache = FinesseCache::default();
+        assert!(cache.placements(&o, &board).iter().all(|(p, _)| !tucked(p)));
+        let under = cache.reachable(&o, &board).iter().find(|p| tucked(&p.piece)).cloned().unwrap();
+        assert_eq!(under.path.last(), Some(&crate::lib::Action::HardDrop));
+        assert!(!under.spun);
+        assert_eq!(cache.optimal_inputs(&under.piece, &board), None);
+    }
+
+    #[test]
+    fn the_cache_searches_again_for_a_new_board() {
+        let mut board = Board::new(10, 20);
+        let o = piece('O');
+        let mut cache = FinesseCache::default();
+        assert!(cache.placements(&o, &board).iter().all(|(p, _)| p.get_tiles().iter().any(|t| t.y() == 19)));
+        for x in 0..10 {
+            board.place(x, 19, Cell::Garbage, 0);
+        }
+        assert!(cache.placements(&o, &board).iter().all(|(p, _)| p.get_tiles().iter().all(|t| t.y() < 19)));
+        assert!(cache.reachable(&o, &board).iter().all(|p| p.piece.get_tiles().iter().all(|t| t.y() < 19)));
+    }
+
+    #[test]
+    fn drills_keep_their_target_until_it_is_hit_cleanly() {
+        let board = Board::new(10, 20);
+        let o = piece('O');
+        let mut cache = FinesseCache::default();
+        let mut drill = Drill::new(7);
+        drill.aim(&o, &board, &mut cache);
+        let target = drill.target().cloned().unwrap();
+
+        assert!(!drill.check(&target, true));
+        drill.aim(&o, &board, &mut cache);
+        assert_eq!(drill.target().map(cells), Some(cells(&target)));
+        assert!(drill.check(&target, false));
+        assert_eq!((drill.attempts(), drill.passed()), (2, 1));
+        assert!(drill.target().is_none());
+    }
+}
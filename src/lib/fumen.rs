@@ -0,0 +1,584 @@
+const ENCODE_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_TABLE: &[u8] = b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const PIECE_NAMES: [char; 9] = ['_', 'I', 'L', 'O', 'Z', 'T', 'J', 'S', 'X'];
+
+const VERSION_PREFIX: &str = "v115@";
+const FIELD_TOP: i32 = 23;
+const FIELD_WIDTH: i32 = 10;
+const FIELD_BLOCKS: usize = 240;
+const MAX_COMMENT_LENGTH: usize = 4095;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rotation {
+    Spawn,
+    Right,
+    Reverse,
+    Left,
+}
+
+#[derive(Clone, Debug)]
+pub struct FumenPiece {
+    pub kind: char,
+    pub rotation: Rotation,
+    pub x: i32,
+    pub y: i32,
+}
+
+// y grows upwards from the bottom row, y = -1 being the garbage row underneath the playfield
+#[derive(Clone, PartialEq)]
+pub struct Field {
+    cells: [u8; FIELD_BLOCKS],
+}
+
+#[derive(Clone)]
+pub struct FumenPage {
+    pub field: Field,
+    pub piece: Option<FumenPiece>,
+    pub comment: String,
+    pub lock: bool,
+    pub rise: bool,
+    pub mirror: bool,
+}
+
+pub struct Fumen {
+    pub pages: Vec<FumenPage>,
+}
+
+struct Reader {
+    values: Vec<u32>,
+    position: usize,
+}
+
+impl Rotation {
+    fn from_value(value: u32) -> Rotation {
+        match value {
+            0 => Rotation::Reverse,
+            1 => Rotation::Right,
+            2 => Rotation::Spawn,
+            _ => Rotation::Left,
+        }
+    }
+
+    fn value(self) -> u32 {
+        match self {
+            Rotation::Reverse => 0,
+            Rotation::Right => 1,
+            Rotation::Spawn => 2,
+            Rotation::Left => 3,
+        }
+    }
+}
+
+impl FumenPiece {
+    pub fn from_cells(kind: char, cells: &[(i32, i32)]) -> Option<FumenPiece> {
+        let mut target = cells.to_vec();
+        target.sort();
+
+        for &rotation in &[Rotation::Spawn, Rotation::Right, Rotation::Reverse, Rotation::Left] {
+            let mut candidate = FumenPiece { kind, rotation, x: 0, y: 0 }.cells();
+            if candidate.len() != target.len() {
+                return None;
+            }
+            candidate.sort();
+            let dx = target[0].0 - candidate[0].0;
+            let dy = target[0].1 - candidate[0].1;
+            if candidate.iter().zip(target.iter()).all(|(c, t)| c.0 + dx == t.0 && c.1 + dy == t.1) {
+                return Some(FumenPiece { kind, rotation, x: dx, y: dy });
+            }
+        }
+        None
+    }
+
+    pub fn cells(&self) -> Vec<(i32, i32)> {
+        let blocks: [(i32, i32); 4] = match self.kind {
+            'I' => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+            'T' => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+            'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            'L' => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+            'J' => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+            'S' => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+            'Z' => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+            _ => return vec![],
+        };
+        blocks.iter()
+            .map(|&(x, y)| match self.rotation {
+                Rotation::Spawn => (x, y),
+                Rotation::Right => (y, -x),
+                Rotation::Reverse => (-x, -y),
+                Rotation::Left => (-y, x),
+            })
+            .map(|(x, y)| (self.x + x, self.y + y))
+            .collect()
+    }
+
+    fn kind_value(&self) -> u32 {
+        PIECE_NAMES.iter().position(|&c| c == self.kind).unwrap_or(0) as u32
+    }
+
+    // fumen stores some pieces relative to a different centre than the SRS one used by cells()
+    fn legacy_offset(&self) -> (i32, i32) {
+        match (self.kind, self.rotation) {
+            ('O', Rotation::Left) => (-1, 1),
+            ('O', Rotation::Reverse) => (-1, 0),
+            ('O', Rotation::Spawn) => (0, 1),
+            ('I', Rotation::Reverse) => (-1, 0),
+            ('I', Rotation::Left) => (0, 1),
+            ('S', Rotation::Spawn) => (0, 1),
+            ('S', Rotation::Right) => (1, 0),
+            ('Z', Rotation::Spawn) => (0, 1),
+            ('Z', Rotation::Left) => (-1, 0),
+            _ => (0, 0),
+        }
+    }
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Field::new()
+    }
+}
+
+impl Field {
+    pub fn new() -> Field {
+        Field { cells: [0; FIELD_BLOCKS] }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> char {
+        PIECE_NAMES[self.cells[Field::index(x, y)] as usize]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, kind: char) {
+        let value = PIECE_NAMES.iter().position(|&c| c == kind).unwrap_or(8);
+        self.cells[Field::index(x, y)] = value as u8;
+    }
+
+    fn index(x: i32, y: i32) -> usize {
+        ((FIELD_TOP - 1 - y) * FIELD_WIDTH + x) as usize
+    }
+
+    fn place(&mut self, piece: &FumenPiece) {
+        for (x, y) in piece.cells() {
+            if (0..FIELD_WIDTH).contains(&x) && (-1..FIELD_TOP).contains(&y) {
+                self.cells[Field::index(x, y)] = piece.kind_value() as u8;
+            }
+        }
+    }
+
+    fn clear_lines(&mut self) {
+        let mut rows: Vec<[u8; 10]> = (0..FIELD_TOP)
+            .map(|y| self.row(y))
+            .filter(|row| row.contains(&0))
+            .collect();
+        rows.resize(FIELD_TOP as usize, [0; 10]);
+        for (y, row) in rows.iter().enumerate() {
+            self.set_row(y as i32, *row);
+        }
+    }
+
+    fn rise(&mut self) {
+        for y in (0..FIELD_TOP).rev() {
+            let below = self.row(y - 1);
+            self.set_row(y, below);
+        }
+        self.set_row(-1, [0; 10]);
+    }
+
+    fn mirror(&mut self) {
+        for y in 0..FIELD_TOP {
+            let mut row = self.row(y);
+            row.reverse();
+            self.set_row(y, row);
+        }
+    }
+
+    fn row(&self, y: i32) -> [u8; 10] {
+        let mut row = [0; 10];
+        let start = Field::index(0, y);
+        row.copy_from_slice(&self.cells[start..start + 10]);
+        row
+    }
+
+    fn set_row(&mut self, y: i32, row: [u8; 10]) {
+        let start = Field::index(0, y);
+        self.cells[start..start + 10].copy_from_slice(&row);
+    }
+}
+
+impl FumenPage {
+    pub fn new(field: Field, piece: Option<FumenPiece>, comment: String) -> FumenPage {
+        FumenPage {
+            field,
+            piece,
+            comment,
+            lock: true,
+            rise: false,
+            mirror: false,
+        }
+    }
+
+    fn resulting_field(&self) -> Field {
+        let mut field = self.field.clone();
+        if self.lock {
+            if let Some(piece) = &self.piece {
+                field.place(piece);
+            }
+            field.clear_lines();
+            if self.rise {
+                field.rise();
+            }
+            if self.mirror {
+                field.mirror();
+            }
+        }
+        field
+    }
+}
+
+impl Fumen {
+    pub fn decode(data: &str) -> Result<Fumen, String> {
+        let start = data.find(VERSION_PREFIX)
+            .ok_or_else(|| "only v115 fumens are supported".to_string())?;
+        let values = data[start + VERSION_PREFIX.len()..].trim()
+            .chars()
+            .filter(|&c| c != '?')
+            .map(|c| ENCODE_TABLE.iter()
+                .position(|&e| e as char == c)
+                .map(|v| v as u32)
+                .ok_or_else(|| format!("unexpected character '{}' in fumen", c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut reader = Reader { values, position: 0 };
+
+        let mut pages = vec![];
+        let mut prev = Field::new();
+        let mut comment = String::new();
+        let mut repeat = 0;
+
+        while !reader.is_empty() {
+            let mut field = prev.clone();
+            if repeat > 0 {
+                repeat -= 1;
+            } else {
+                let mut index = 0;
+                while index < FIELD_BLOCKS {
+                    let value = reader.poll(2)?;
+                    let diff = (value / FIELD_BLOCKS as u32) as i32 - 8;
+                    let count = value as usize % FIELD_BLOCKS + 1;
+                    if diff == 0 && count == FIELD_BLOCKS {
+                        repeat = reader.poll(1)?;
+                    }
+                    if index + count > FIELD_BLOCKS {
+                        return Err("fumen field data overflows the board".to_string());
+                    }
+                    for cell in field.cells[index..index + count].iter_mut() {
+                        let updated = *cell as i32 + diff;
+                        if updated < 0 || updated >= PIECE_NAMES.len() as i32 {
+                            return Err("fumen field data is corrupt".to_string());
+                        }
+                        *cell = updated as u8;
+                    }
+                    index += count;
+                }
+            }
+
+            let action = reader.poll(3)?;
+            let kind = PIECE_NAMES[(action % 8) as usize];
+            let rotation = Rotation::from_value(action / 8 % 4);
+            let position = (action / 32 % FIELD_BLOCKS as u32) as i32;
+            let flags = action / (32 * FIELD_BLOCKS as u32);
+
+            if flags & 8 != 0 {
+                let length = reader.poll(2)? as usize;
+                let mut escaped = String::new();
+                for _ in 0..length.div_ceil(4) {
+                    let mut value = reader.poll(5)? as usize;
+                    for _ in 0..4 {
+                        escaped.push(COMMENT_TABLE[value % COMMENT_TABLE.len()] as char);
+                        value /= COMMENT_TABLE.len();
+                    }
+                }
+                escaped.truncate(length);
+                comment = unescape(&escaped);
+            }
+
+            let piece = if kind == '_' {
+                None
+            } else {
+                let mut piece = FumenPiece {
+                    kind,
+                    rotation,
+                    x: position % FIELD_WIDTH,
+                    y: FIELD_TOP - position / FIELD_WIDTH - 1,
+                };
+                let (dx, dy) = piece.legacy_offset();
+                piece.x -= dx;
+                piece.y -= dy;
+                Some(piece)
+            };
+
+            let page = FumenPage {
+                field,
+                piece,
+                comment: comment.clone(),
+                lock: flags & 16 == 0,
+                rise: flags & 1 != 0,
+                mirror: flags & 2 != 0,
+            };
+            prev = page.resulting_field();
+            pages.push(page);
+        }
+
+        if pages.is_empty() {
+            return Err("fumen contains no pages".to_string());
+        }
+
+        Ok(Fumen { pages })
+    }
+
+    pub fn encode(&self) -> String {
+        let mut values = vec![];
+        let mut prev = Field::new();
+        let mut prev_comment = String::new();
+        let mut repeat_index: Option<usize> = None;
+
+        for (i, page) in self.pages.iter().enumerate() {
+            let (changed, field_values) = encode_field(&prev, &page.field);
+            match repeat_index {
+                Some(index) if !changed && values[index] < ENCODE_TABLE.len() as u32 - 1 => values[index] += 1,
+                _ => {
+                    values.extend(field_values);
+                    if changed {
+                        repeat_index = None;
+                    } else {
+                        values.push(0);
+                        repeat_index = Some(values.len() - 1);
+                    }
+                }
+            }
+
+            let has_comment = page.comment != prev_comment;
+            let (kind, rotation, position) = match &page.piece {
+                Some(piece) => {
+                    let (dx, dy) = piece.legacy_offset();
+                    let position = (FIELD_TOP - (piece.y + dy) - 1) * FIELD_WIDTH + piece.x + dx;
+                    (piece.kind_value(), piece.rotation.value(), position as u32)
+                }
+                None => (0, 0, 0),
+            };
+            let mut action = if page.lock { 0 } else { 1 };
+            action = action * 2 + has_comment as u32;
+            action = action * 2 + (i == 0) as u32;
+            action = action * 2 + page.mirror as u32;
+            action = action * 2 + page.rise as u32;
+            action = action * FIELD_BLOCKS as u32 + position;
+            action = action * 4 + rotation;
+            action = action * 8 + kind;
+            push(&mut values, action, 3);
+
+            if has_comment {
+                let mut escaped = escape(&page.comment);
+                escaped.truncate(MAX_COMMENT_LENGTH);
+                push(&mut values, escaped.len() as u32, 2);
+                for chunk in escaped.as_bytes().chunks(4) {
+                    let mut value = 0;
+                    for &c in chunk.iter().rev() {
+                        let index = COMMENT_TABLE.iter().position(|&t| t == c).unwrap_or(0);
+                        value = value * COMMENT_TABLE.len() + index;
+                    }
+                    push(&mut values, value as u32, 5);
+                }
+                prev_comment = page.comment.clone();
+            }
+
+            prev = page.resulting_field();
+        }
+
+        let data: String = values.iter().map(|&v| ENCODE_TABLE[v as usize] as char).collect();
+        let mut out = VERSION_PREFIX.to_string();
+        if data.len() <= 42 {
+            out.push_str(&data);
+        } else {
+            out.push_str(&data[..42]);
+            for chunk in data.as_bytes()[42..].chunks(47) {
+                out.push('?');
+                out.push_str(std::str::from_utf8(chunk).unwrap());
+            }
+        }
+        out
+    }
+}
+
+impl Reader {
+    fn is_empty(&self) -> bool {
+        self.position >= self.values.len()
+    }
+
+    fn poll(&mut self, digits: usize) -> Result<u32, String> {
+        if self.position + digits > self.values.len() {
+            return Err("fumen ended unexpectedly".to_string());
+        }
+        let value = self.values[self.position..self.position + digits].iter()
+            .rev()
+            .fold(0, |acc, &v| acc * ENCODE_TABLE.len() as u32 + v);
+        self.position += digits;
+        Ok(value)
+    }
+}
+
+fn push(values: &mut Vec<u32>, mut value: u32, digits: usize) {
+    for _ in 0..digits {
+        values.push(value % ENCODE_TABLE.len() as u32);
+        value /= ENCODE_TABLE.len() as u32;
+    }
+}
+
+fn encode_field(prev: &Field, current: &Field) -> (bool, Vec<u32>) {
+    let mut values = vec![];
+    let diff = |i: usize| current.cells[i] as u32 + 8 - prev.cells[i] as u32;
+
+    let mut run_diff = diff(0);
+    let mut run_length = 0;
+    for i in 1..FIELD_BLOCKS {
+        let d = diff(i);
+        if d != run_diff {
+            push(&mut values, run_diff * FIELD_BLOCKS as u32 + run_length, 2);
+            run_diff = d;
+            run_length = 0;
+        } else {
+            run_length += 1;
+        }
+    }
+    push(&mut values, run_diff * FIELD_BLOCKS as u32 + run_length, 2);
+
+    (!(run_diff == 8 && run_length == FIELD_BLOCKS as u32 - 1), values)
+}
+
+// comments are stored with the semantics of javascript's escape()
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) {
+            out.push(c);
+        } else if (c as u32) < 256 {
+            out.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                out.push_str(&format!("%u{:04X}", unit));
+            }
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    let mut units = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let parsed = if rest.starts_with("%u") && rest.len() >= 6 {
+            u16::from_str_radix(&rest[2..6], 16).ok().map(|u| (u, 6))
+        } else if c == '%' && rest.len() >= 3 {
+            u16::from_str_radix(&rest[1..3], 16).ok().map(|u| (u, 3))
+        } else {
+            None
+        };
+        match parsed {
+            Some((unit, length)) => {
+                units.push(unit);
+                rest = &rest[length..];
+            }
+            None => {
+                units.push(c as u16);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(field: Field, piece: Option<FumenPiece>, comment: &str) -> FumenPage {
+        FumenPage::new(field, piece, comment.to_string())
+    }
+
+    #[test]
+    fn empty_field_encodes_to_the_known_string() {
+        let fumen = Fumen { pages: vec![page(Field::new(), None, "")] };
+        assert_eq!(fumen.encode(), "v115@vhAAgH");
+    }
+
+    #[test]
+    fn payload_of_41_characters_round_trips() {
+        // nine isolated cells make nineteen runs of two characters each, plus three for the action
+        let mut field = Field::new();
+        for x in (0..10).step_by(2) {
+            field.set(x, 0, 'X');
+        }
+        for x in (0..8).step_by(2) {
+            field.set(x, 1, 'X');
+        }
+        let fumen = Fumen { pages: vec![page(field.clone(), None, "")] };
+        let encoded = fumen.encode();
+        assert_eq!(encoded.len(), VERSION_PREFIX.len() + 41);
+
+        let decoded = Fumen::decode(&encoded).unwrap();
+        assert!(decoded.pages[0].field == field);
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn long_fumens_are_split_and_round_trip() {
+        let mut pages = vec![];
+        let mut field = Field::new();
+        for i in 0..10 {
+            field.set(i, 0, 'X');
+            pages.push(page(field.clone(), None, &format!("page {}", i)));
+        }
+        let encoded = Fumen { pages }.encode();
+        assert!(encoded.contains('?'));
+
+        let decoded = Fumen::decode(&encoded).unwrap();
+        assert_eq!(decoded.pages.len(), 10);
+        assert_eq!(decoded.pages[9].comment, "page 9");
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn pieces_and_comments_round_trip() {
+        let piece = FumenPiece::from_cells('T', &[(4, 0), (5, 0), (6, 0), (5, 1)]).unwrap();
+        let fumen = Fumen { pages: vec![page(Field::new(), Some(piece), "t-spin? 100%")] };
+        let decoded = Fumen::decode(&fumen.encode()).unwrap();
+
+        let page = &decoded.pages[0];
+        assert_eq!(page.comment, "t-spin? 100%");
+        let decoded_piece = page.piece.as_ref().unwrap();
+        assert_eq!(decoded_piece.kind, 'T');
+        assert_eq!(decoded_piece.rotation, Rotation::Spawn);
+        let mut cells = decoded_piece.cells();
+        cells.sort();
+        assert_eq!(cells, vec![(4, 0), (5, 0), (5, 1), (6, 0)]);
+    }
+
+    #[test]
+    fn locked_pieces_carry_over_to_the_next_page() {
+        let piece = FumenPiece::from_cells('I', &[(0, 0), (1, 0), (2, 0), (3, 0)]).unwrap();
+        let first = page(Field::new(), Some(piece), "");
+        let second = page(first.resulting_field(), None, "");
+        let encoded = Fumen { pages: vec![first, second] }.encode();
+        // the second field is the first with its piece locked, so only its action is stored
+        assert_eq!(encoded.len(), "v115@vhAAgH".len() + 3);
+
+        let decoded = Fumen::decode(&encoded).unwrap();
+        assert_eq!(decoded.pages[1].field.get(0, 0), 'I');
+        assert_eq!(decoded.pages[1].field.get(4, 0), '_');
+    }
+
+    #[test]
+    fn decode_rejects_bad_input() {
+        assert!(Fumen::decode("v110@vhAAgH").is_err());
+        assert!(Fumen::decode("v115@vh!AgH").is_err());
+        assert!(Fumen::decode("v115@vhA").is_err());
+    }
+}
@@ -1,202 +1,1099 @@
 use std::sync::mpsc::SyncSender;
+use std::time::Duration;
 
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
 use sdl2::render::WindowCanvas;
+use serde::{Deserialize, Serialize};
 
-use crate::lib::{PieceBag, PlayerPiece, Sound};
+use crate::lib::{Action, AttackTable, Board, Cell, Drill, Field, FinesseCache, FinesseInput, Fumen, FumenPage, FumenPiece, G, GarbageGenerator, GarbageQueue, MASTER_MAX_LEVEL, MASTER_SECTION_LEVELS,
+                 Opener, OpenerTemplate, PieceBag, Position, PieceSet, PlayerPiece, Settings, Sound, Speed, Stats};
+use crate::lib::finesse;
+use crate::lib::search::Placement;
 
+const FRAMES_PER_SECOND: u64 = 60;
+const SPLIT_LINES: u64 = 10;
+const WARNING_MS: u64 = 10_000;
+const LEVEL_LINES: u64 = 10;
+const BOARD_WIDTH: usize = 10;
+const BOARD_HEIGHT: usize = 20;
+const DANGER_ROWS: usize = 4;
+const DIG_VISIBLE_ROWS: u64 = 10;
+const ROLL_FRAMES: u64 = 3600;
+const LOCK_FLASH_FRAMES: i64 = 6;
+const FADE_FRAMES: i64 = 30;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Goal {
+    None,
+    Lines(u64),
+    Time(u64),
+    Garbage,
+    Roll,
+    PerfectClear,
+    TSpin(usize),
+    Opener,
+}
+
+// fields missing from an older save take their values from a fresh game
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct GameState {
-    tiles: [[bool; 20]; 10],
+    board: Board,
     turns: i64,
+    seats: Vec<Seat>,
+    cleared: u64,
+    pieces: u64,
+    stats: Stats,
+    goal: Goal,
+    speed: Speed,
+    start_level: u64,
+    level: u64,
+    splits: Vec<u64>,
+    section_times: Vec<u64>,
+    garbage: Option<GarbageGenerator>,
+    garbage_left: u64,
+    incoming: GarbageQueue,
+    outgoing: u64,
+    attack: AttackTable,
+    roll: Option<u64>,
+    fade: Option<i64>,
+    scale: i32,
+    hold: bool,
+    achieved: bool,
+    hint: Option<Vec<FinesseInput>>,
+    drill: Option<Drill>,
+    opener: Option<OpenerTemplate>,
+    #[serde(skip)]
+    finesse: FinesseCache,
+}
+
+// everything that belongs to one player's pieces; co-op games have a seat for each player, sharing the board
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+struct Seat {
     active: Option<PlayerPiece>,
     bag: PieceBag,
     cleared: u64,
+    spawn_delay: u32,
+    lock_timer: u32,
+    lock_resets: u32,
+    fall: u32,
+    dropped: bool,
+    last_rotated: bool,
+    // presses spent on the piece in play, so one player's keys don't count against the other's finesse
+    keys: u64,
 }
 
 impl GameState {
-    pub fn new() -> Self {
+    pub fn new(goal: Goal, speed: Speed, start_level: u64, pieces: &PieceSet) -> Self {
         GameState {
-            tiles: [[false; 20]; 10],
+            board: Board::new(BOARD_WIDTH, BOARD_HEIGHT),
             turns: 0,
-            active: None,
-            bag: PieceBag::new(),
+            seats: vec![Seat::new(PieceBag::new(pieces))],
             cleared: 0,
+            pieces: 0,
+            stats: Stats::default(),
+            goal,
+            speed,
+            start_level,
+            level: start_level,
+            splits: vec![],
+            section_times: vec![],
+            garbage: None,
+            garbage_left: 0,
+            incoming: GarbageQueue::default(),
+            outgoing: 0,
+            attack: AttackTable::default(),
+            roll: None,
+            fade: None,
+            scale: 1,
+            hold: true,
+            achieved: false,
+            hint: None,
+            drill: None,
+            opener: None,
+            finesse: FinesseCache::default(),
         }
     }
 
-    pub fn draw(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
-        canvas.set_draw_color(Color::RGB(255, 255, 0));
+    pub fn from_fumen(data: &str) -> Result<Self, String> {
+        let fumen = Fumen::decode(data)?;
+        let field = &fumen.pages[0].field;
 
-        for i in 0..self.tiles.len() {
-            for j in 0..self.tiles[0].len() {
-                if self.tiles[i][j] {
-                    canvas.fill_rect(Rect::new((i * 40) as i32, (j * 40) as i32, 40, 40))?;
+        let mut board = Board::new(BOARD_WIDTH, BOARD_HEIGHT);
+        for x in 0..BOARD_WIDTH {
+            for y in 0..23 {
+                let cell = match field.get(x as i32, y) {
+                    '_' => continue,
+                    'X' => Cell::Garbage,
+                    _ => Cell::Block,
+                };
+                if y as usize >= BOARD_HEIGHT {
+                    return Err("fumen field is taller than the board".to_string());
                 }
+                board.set(x, BOARD_HEIGHT - 1 - y as usize, cell);
             }
         }
 
-        if let Some(piece) = &self.active {
-            piece.draw(canvas, &self.tiles)?;
+        let pieces = PieceSet::standard();
+        let queue = fumen.pages.iter()
+            .filter_map(|page| page.piece.as_ref())
+            .filter_map(|piece| pieces.find(piece.kind))
+            .collect();
+
+        let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &pieces);
+        game_state.board = board;
+        game_state.seats[0] = Seat::new(PieceBag::with_queue(&pieces, queue));
+        Ok(game_state)
+    }
+
+    pub fn to_fumen(&self, comment: &str) -> String {
+        let mut field = Field::new();
+        for x in 0..self.board.width() {
+            for y in 0..self.board.height() {
+                if self.board.get(x, y) != Cell::Empty {
+                    field.set(x as i32, (self.board.height() - 1 - y) as i32, 'X');
+                }
+            }
         }
 
-        canvas.set_draw_color(Color::RGB(0, 0, 255));
+        let top = self.board.height() as i32 - 1;
+        let piece = self.seats[0].active.as_ref().and_then(|piece| {
+            let cells: Vec<_> = piece.get_tiles().iter()
+                .map(|p| (p.x(), top - p.y()))
+                .collect();
+            FumenPiece::from_cells(piece.name(), &cells)
+        });
+
+        Fumen { pages: vec![FumenPage::new(field, piece, comment.to_string())] }.encode()
+    }
+
+    pub fn pieces(&self) -> u64 {
+        self.pieces
+    }
+
+    pub fn cleared(&self) -> u64 {
+        self.cleared
+    }
 
-        for i in 0..=self.tiles.len() {
-            canvas.draw_line(Point::new((i * 40) as i32, 0), Point::new((i * 40) as i32, 800))?;
+    // the main loop runs a frame every sixtieth of a second of real time, so counting them keeps time
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.turns as u64 * 1000 / FRAMES_PER_SECOND)
+    }
+
+    pub fn finished(&self) -> bool {
+        match self.goal {
+            Goal::None => false,
+            Goal::Lines(lines) => self.cleared >= lines,
+            Goal::Time(ms) => self.elapsed().as_millis() as u64 >= ms,
+            Goal::Garbage => self.garbage_left == 0 && self.board.garbage_rows() == 0,
+            Goal::Roll => self.roll == Some(0),
+            Goal::PerfectClear | Goal::TSpin(_) => self.achieved,
+            Goal::Opener => self.opener.as_ref().is_some_and(|o| o.complete()),
         }
+    }
+
+    // elapsed milliseconds at which each hundred levels of a master game were passed
+    pub fn section_times(&self) -> &[u64] {
+        &self.section_times
+    }
+
+    pub fn roll_remaining(&self) -> Option<Duration> {
+        self.roll.map(|frames| Duration::from_millis(frames * 1000 / FRAMES_PER_SECOND))
+    }
+
+    // fills the bottom of the board from the generator and keeps it topped up as rows are dug out
+    pub fn add_garbage(&mut self, generator: GarbageGenerator, rows: u64) {
+        self.garbage = Some(generator);
+        self.garbage_left = rows;
+        self.raise_garbage();
+    }
 
-        for j in 0..=self.tiles[0].len() {
-            canvas.draw_line(Point::new(0, (j * 40) as i32), Point::new(400, (j * 40) as i32))?;
+    pub fn garbage_left(&self) -> u64 {
+        self.garbage_left + self.board.garbage_rows() as u64
+    }
+
+    fn raise_garbage(&mut self) -> bool {
+        let generator = match &mut self.garbage {
+            Some(generator) => generator,
+            None => return true,
+        };
+        while self.garbage_left > 0 && (self.board.garbage_rows() as u64) < DIG_VISIBLE_ROWS {
+            self.garbage_left -= 1;
+            if !self.board.push_row(generator.next_row(self.board.width())) {
+                return false;
+            }
         }
+        true
+    }
 
-        canvas.set_draw_color(Color::RGB(255, 0, 0));
-        canvas.draw_line(Point::new(0, 160), Point::new(400, 160))?;
+    // delay is how many frames incoming garbage waits before it can rise
+    pub fn set_attack(&mut self, table: AttackTable, delay: u32) {
+        self.attack = table;
+        self.incoming = GarbageQueue::new(delay);
+    }
 
-        canvas.set_draw_color(Color::RGB(128, 50, 200));
+    // attack from an opponent rises out of the floor once its delay is over and a piece locks without clearing
+    pub fn receive_garbage(&mut self, rows: u64) {
+        self.incoming.push(rows, self.turns);
+    }
 
-        for square in self.bag.peek().base().iter() {
-            canvas.fill_rect(Rect::new(500 + 50 * square.x(), 100 + 50 * square.y(), 50, 50))?;
+    pub fn incoming(&self) -> u64 {
+        self.incoming.total()
+    }
+
+    // the attack left over after cancelling incoming garbage since the last call, for sending on to an opponent
+    pub fn take_attack(&mut self) -> u64 {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    fn raise_incoming(&mut self) -> bool {
+        let generator = match &mut self.garbage {
+            Some(generator) => generator,
+            None => return true,
+        };
+        for rows in self.incoming.take_ready(self.turns) {
+            generator.new_batch();
+            for _ in 0..rows {
+                if !self.board.push_row(generator.next_row(self.board.width())) {
+                    return false;
+                }
+            }
         }
+        true
+    }
 
-        canvas.set_draw_color(Color::RGB(0, 0, 255));
-        for i in 0..=4 {
-            canvas.draw_line(Point::new(500, 100 + i * 50), Point::new(700, 100 + i * 50))?;
-            canvas.draw_line(Point::new(500 + i * 50, 100), Point::new(500 + i * 50, 300))?;
+    pub fn reseed(&mut self, seed: u64) {
+        for (i, seat) in self.seats.iter_mut().enumerate() {
+            seat.bag.reseed(seed.wrapping_add(i as u64));
         }
+        if let Some(garbage) = &mut self.garbage {
+            garbage.reseed(seed);
+        }
+    }
 
-        Ok(())
+    // co-op widens the board and gives every player their own seat, spawning pieces over their own stretch of
+    // it; each seat's bag is seeded one on from the last, so the game's seed still deals the same pieces
+    pub fn set_players(&mut self, players: usize, width: usize) {
+        self.board = Board::new(width, self.board.height());
+        let bag = self.seats[0].bag.clone();
+        let seed = self.seed();
+        self.seats = (0..players)
+            .map(|i| {
+                let mut bag = bag.clone();
+                bag.reseed(seed.wrapping_add(i as u64));
+                bag.set_column((i * width / players) as i32 + (width / players) as i32 / 2 - BOARD_WIDTH as i32 / 2);
+                Seat::new(bag)
+            })
+            .collect();
     }
 
-    pub fn handle(&mut self, event: Event) -> bool {
-        match event {
-            Event::KeyDown { keycode: Some(Keycode::A), .. }
-            | Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                if let Some(piece) = &self.active {
-                    if let Some(new_piece) = piece.go_left(&self.tiles) {
-                        self.active = Some(new_piece);
-                    }
-                }
+    pub fn players(&self) -> usize {
+        self.seats.len()
+    }
+
+    pub fn cleared_by(&self, seat: usize) -> u64 {
+        self.seats.get(seat).map_or(0, |s| s.cleared)
+    }
+
+    pub fn board_width(&self) -> usize {
+        self.board.width()
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    pub fn set_fade(&mut self, frames: Option<i64>) {
+        self.fade = frames;
+    }
+
+    // big pieces are made of scale x scale cells, and lines only count once per scale rows
+    pub fn set_scale(&mut self, scale: i32) {
+        self.scale = scale;
+    }
+
+    // a puzzle starts from a known position with a fixed queue, and fails once the queue runs out
+    pub fn set_puzzle(&mut self, goal: Goal, pieces: &PieceSet, queue: Vec<PlayerPiece>, hold: bool) {
+        self.goal = goal;
+        self.seats[0].bag = PieceBag::fixed(pieces, queue);
+        self.hold = hold;
+    }
+
+    // rows are drawn top to bottom with the last one on the floor; '_' is empty and 'X' garbage
+    pub fn set_rows(&mut self, rows: &[String]) -> Result<(), String> {
+        let (width, height) = (self.board.width(), self.board.height());
+        if rows.len() > height {
+            return Err(format!("{} rows won't fit on a board {} rows high", rows.len(), height));
+        }
+        for (i, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(format!("row {:?} should be {} cells wide", row, width));
             }
-            Event::KeyDown { keycode: Some(Keycode::D), .. }
-            | Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                if let Some(piece) = &self.active {
-                    if let Some(new_piece) = piece.go_right(&self.tiles) {
-                        self.active = Some(new_piece);
-                    }
-                }
+            for (x, c) in row.chars().enumerate() {
+                let cell = match c {
+                    '_' => Cell::Empty,
+                    'X' => Cell::Garbage,
+                    _ => Cell::Block,
+                };
+                self.board.set(x, height - rows.len() + i, cell);
             }
-            Event::KeyDown { keycode: Some(Keycode::S), .. }
-            | Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                'loader: loop {
-                    match &self.active {
-                        Some(piece) => {
-                            match piece.go_down(&self.tiles) {
-                                Some(new_piece) => self.active = Some(new_piece),
-                                None => break 'loader
-                            }
+        }
+        Ok(())
+    }
+
+    // the finesse trainer turns hold off, since holding would swap out the piece being drilled
+    pub fn set_trainer(&mut self) {
+        self.drill = Some(Drill::new(self.seed()));
+        self.hold = false;
+    }
+
+    // the template branches on the first bag, so this has to happen before the first piece is dealt
+    pub fn set_opener(&mut self, opener: &Opener, mirrored: bool) -> Result<(), String> {
+        let upcoming: Vec<char> = self.seats[0].bag.peek_many(7).iter().map(|p| p.name()).collect();
+        self.opener = Some(opener.template(mirrored, &upcoming, &self.board)?);
+        self.goal = Goal::Opener;
+        Ok(())
+    }
+
+    pub fn opener(&self) -> Option<&OpenerTemplate> {
+        self.opener.as_ref()
+    }
+
+    // what a bot playing the seat gets to see: its piece, the board around it and up to previews pieces after it
+    pub fn position(&self, seat: usize, previews: usize) -> Option<Position> {
+        let current = &self.seats[seat];
+        current.active.as_ref().map(|piece| Position {
+            board: self.obstacles(seat),
+            piece: piece.clone(),
+            queue: current.bag.peek_many(previews).iter().map(|p| p.scaled(self.scale)).collect(),
+            hold: self.hold,
+            combo: self.stats.combo(),
+            back_to_back: self.stats.back_to_back(),
+        })
+    }
+
+    pub fn drill(&self) -> Option<&Drill> {
+        self.drill.as_ref()
+    }
+
+    // the fastest way to have placed the last piece, if it was placed with wasted keys
+    pub fn finesse_hint(&self) -> Option<String> {
+        self.hint.as_ref().map(|inputs| finesse::describe(inputs))
+    }
+
+    pub fn pieces_left(&self) -> Option<usize> {
+        let seat = &self.seats[0];
+        seat.bag.left().map(|left| left + seat.active.is_some() as usize)
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        match self.goal {
+            Goal::Time(ms) => Some(Duration::from_millis(ms).checked_sub(self.elapsed()).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    // elapsed milliseconds each time another ten lines were cleared
+    pub fn splits(&self) -> &[u64] {
+        &self.splits
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn level(&self) -> u64 {
+        self.level
+    }
+
+    pub fn pps(&self) -> f64 {
+        let seconds = self.elapsed().as_secs_f64();
+        if seconds > 0.0 { self.pieces as f64 / seconds } else { 0.0 }
+    }
+
+    pub fn kpp(&self) -> f64 {
+        if self.pieces > 0 { self.stats.keys as f64 / self.pieces as f64 } else { 0.0 }
+    }
+
+    pub fn apm(&self) -> f64 {
+        let minutes = self.elapsed().as_secs_f64() / 60.0;
+        if minutes > 0.0 { self.stats.attack as f64 / minutes } else { 0.0 }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seats[0].bag.seed()
+    }
+
+    // scores are kept apart for each piece set
+    pub fn ruleset(&self) -> &str {
+        self.seats[0].bag.set_name()
+    }
+
+    // reveal shows the whole stack even when locked blocks would otherwise have faded away
+    pub fn draw(&self, canvas: &mut WindowCanvas, settings: &Settings, reveal: bool) -> Result<(), String> {
+        let theme = &settings.theme;
+
+        for i in 0..self.board.width() {
+            for j in 0..self.board.height() {
+                let color = match (self.board.get(i, j), self.fade) {
+                    (Cell::Empty, _) => continue,
+                    (Cell::Garbage, _) => theme.garbage.0,
+                    (Cell::Block, None) => theme.board.0,
+                    (Cell::Block, _) if reveal => theme.board.0,
+                    (Cell::Block, Some(fade)) => {
+                        let age = self.turns - self.board.placed_at(i, j);
+                        if age < LOCK_FLASH_FRAMES {
+                            theme.flash.0
+                        } else if age < fade {
+                            theme.board.0
+                        } else if fade > 0 && age < fade + FADE_FRAMES {
+                            blend(theme.board.0, theme.background.0, (age - fade) as f32 / FADE_FRAMES as f32)
+                        } else {
+                            continue;
                         }
-                        None => break 'loader
                     }
-                }
+                };
+                canvas.set_draw_color(color);
+                canvas.fill_rect(Rect::new((i * 40) as i32, (j * 40) as i32, 40, 40))?;
             }
-            Event::KeyDown { keycode: Some(Keycode::R), .. }
-            | Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                if let Some(p) = &self.active {
-                    if let Some(new_p) = p.rotate(&self.tiles) {
-                        self.active = Some(new_p);
-                    }
-                }
+        }
+
+        // the rest of the opener shows through faintly, with the last piece that strayed from it outlined
+        if let Some(opener) = &self.opener {
+            canvas.set_draw_color(blend(theme.piece.0, theme.background.0, 0.75));
+            for &(x, y) in opener.pending().filter(|&&(x, y)| self.board.is_free(x, y)) {
+                canvas.fill_rect(Rect::new(x * 40, y * 40, 40, 40))?;
             }
-            Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
-                match &self.active {
-                    Some(piece) => {
-                        self.active = Some(self.bag.swap(piece.clone()));
-                    }
-                    None => ()
+            canvas.set_draw_color(theme.danger.0);
+            for &(x, y) in opener.strayed() {
+                canvas.draw_rect(Rect::new(x * 40 + 3, y * 40 + 3, 34, 34))?;
+            }
+        }
+
+        for (i, seat) in self.seats.iter().enumerate() {
+            if let Some(piece) = &seat.active {
+                piece.draw(canvas, &self.obstacles(i), theme, settings.gameplay.ghost)?;
+            }
+        }
+
+        if let Some(target) = self.drill.as_ref().and_then(|d| d.target()) {
+            canvas.set_draw_color(theme.flash.0);
+            for p in target.get_tiles() {
+                canvas.draw_rect(Rect::new(p.x() * 40 + 3, p.y() * 40 + 3, 34, 34))?;
+            }
+        }
+
+        canvas.set_draw_color(theme.grid.0);
+
+        let (width, height) = (self.board.width() as i32 * 40, self.board.height() as i32 * 40);
+        for i in 0..=self.board.width() {
+            canvas.draw_line(Point::new((i * 40) as i32, 0), Point::new((i * 40) as i32, height))?;
+        }
+
+        for j in 0..=self.board.height() {
+            canvas.draw_line(Point::new(0, (j * 40) as i32), Point::new(width, (j * 40) as i32))?;
+        }
+
+        canvas.set_draw_color(theme.danger.0);
+        canvas.draw_line(Point::new(0, DANGER_ROWS as i32 * 40), Point::new(width, DANGER_ROWS as i32 * 40))?;
+
+        // the garbage meter, in the danger colour for rows that will rise with the next piece that doesn't clear
+        let ready = self.incoming.ready(self.turns).min(self.board.height() as u64) as i32;
+        let waiting = self.incoming.total().min(self.board.height() as u64) as i32 - ready;
+        for (rows, bottom, color) in [(ready, height, theme.danger.0), (waiting, height - 40 * ready, theme.garbage.0)].iter() {
+            if *rows > 0 {
+                canvas.set_draw_color(*color);
+                canvas.fill_rect(Rect::new(width + 10, bottom - 40 * rows, 20, 40 * *rows as u32))?;
+            }
+        }
+
+        // previews shrink their cells so pieces with bigger boxes still fit in the same space; with more than one
+        // seat there's only room for each player's next piece, side by side
+        let count = if self.seats.len() > 1 { 1 } else { settings.gameplay.preview_count };
+        for (seat_index, seat) in self.seats.iter().enumerate() {
+            let left = width + 100 + 220 * seat_index as i32;
+            let previews = seat.bag.peek_many(count);
+            if let Some(next) = previews.first() {
+                let cells = next.box_size().max(4) as i32;
+                let size = 200 / cells;
+                canvas.set_draw_color(next.color().unwrap_or(theme.piece.0));
+                for square in next.base().iter() {
+                    canvas.fill_rect(Rect::new(left + size * square.x(), 100 + size * square.y(), size as u32, size as u32))?;
+                }
+
+                canvas.set_draw_color(theme.grid.0);
+                for i in 0..=cells {
+                    canvas.draw_line(Point::new(left, 100 + i * size), Point::new(left + cells * size, 100 + i * size))?;
+                    canvas.draw_line(Point::new(left + i * size, 100), Point::new(left + i * size, 100 + cells * size))?;
                 }
             }
-            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
-                let x = (x / 40) as usize;
-                let y = (y / 40) as usize;
-                if x < 10 {
-                    self.tiles[x][y] = !self.tiles[x][y];
+
+            for (i, piece) in previews.iter().enumerate().skip(1) {
+                let top = 100 + 90 * (i as i32 - 1);
+                let size = 80 / piece.box_size().max(4) as i32;
+                canvas.set_draw_color(piece.color().unwrap_or(theme.piece.0));
+                for square in piece.base().iter() {
+                    canvas.fill_rect(Rect::new(left + 210 + size * square.x(), top + size * square.y(), size as u32, size as u32))?;
                 }
             }
-            Event::MouseMotion { mousestate, x, y, .. } => {
-                if mousestate.left() {
-                    let x = (x / 40) as usize;
-                    let y = (y / 40) as usize;
-                    if x < 10 {
-                        self.tiles[x][y] = true;
+        }
+
+        Ok(())
+    }
+
+    // a key press, as opposed to an auto-repeated action, counts towards kpp and finesse
+    pub fn press(&mut self, action: Action) {
+        self.press_as(0, action);
+    }
+
+    pub fn press_as(&mut self, seat: usize, action: Action) {
+        if self.seats[seat].active.is_some() {
+            self.stats.keys += 1;
+            self.seats[seat].keys += 1;
+        }
+        self.apply_as(seat, action);
+    }
+
+    pub fn apply(&mut self, action: Action) {
+        self.apply_as(0, action);
+    }
+
+    pub fn apply_as(&mut self, seat: usize, action: Action) {
+        let piece = match &self.seats[seat].active {
+            Some(piece) => piece.clone(),
+            None => return,
+        };
+        let board = self.obstacles(seat);
+        let lock_resets = self.speed.timing(self.level).lock_resets;
+        let current = &mut self.seats[seat];
+
+        let moved = match action {
+            Action::MoveLeft => piece.go_left(&board),
+            Action::MoveRight => piece.go_right(&board),
+            Action::SoftDrop => piece.go_down(&board),
+            Action::HardDrop => {
+                current.dropped = true;
+                Some(piece.lowest_possible_position(&board)).filter(|p| p.anchor() != piece.anchor())
+            }
+            Action::Rotate => piece.rotate(&board),
+            Action::Hold if !self.hold => return,
+            Action::Hold => {
+                current.active = Some(current.bag.swap(piece).scaled(self.scale));
+                current.reset_timers();
+                return;
+            }
+        };
+
+        if let Some(new_piece) = moved {
+            if new_piece.anchor().y() > piece.anchor().y() {
+                current.lock_timer = 0;
+                current.lock_resets = 0;
+            } else if current.lock_timer > 0 && current.lock_resets < lock_resets {
+                current.lock_timer = 0;
+                current.lock_resets += 1;
+            }
+            current.last_rotated = action == Action::Rotate;
+            current.active = Some(new_piece);
+        }
+    }
+
+    // the board as one seat sees it, with every other seat's piece in the way
+    fn obstacles(&self, seat: usize) -> Board {
+        let mut board = self.board.clone();
+        for (_, other) in self.seats.iter().enumerate().filter(|&(i, _)| i != seat) {
+            if let Some(piece) = &other.active {
+                for p in piece.get_tiles() {
+                    if board.is_free(p.x(), p.y()) {
+                        board.set(p.x() as usize, p.y() as usize, Cell::Block);
                     }
                 }
             }
-            _ => ()
         }
-        true
+        board
+    }
+
+    pub fn edit(&mut self, event: &Event) -> Option<(usize, usize, bool)> {
+        let (x, y, toggle) = match *event {
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => (x, y, true),
+            Event::MouseMotion { mousestate, x, y, .. } if mousestate.left() => (x, y, false),
+            _ => return None,
+        };
+
+        let (x, y) = ((x / 40) as usize, (y / 40) as usize);
+        if x >= self.board.width() || y >= self.board.height() {
+            return None;
+        }
+        let filled = !toggle || self.board.get(x, y) == Cell::Empty;
+        self.set_tile(x, y, filled);
+        Some((x, y, filled))
     }
 
-    pub fn update(&mut self, audio: SyncSender<Sound>) -> Option<u64> {
+    pub fn set_tile(&mut self, x: usize, y: usize, filled: bool) {
+        self.board.set(x, y, if filled { Cell::Block } else { Cell::Empty });
+    }
+
+    pub fn update(&mut self, audio: SyncSender<Sound>, soft_drop_factor: u64) -> Option<u64> {
+        self.update_seats(audio, &[soft_drop_factor])
+    }
+
+    // soft drop factors go by seat, and any seat left out falls at the normal speed
+    pub fn update_seats(&mut self, audio: SyncSender<Sound>, soft_drop_factors: &[u64]) -> Option<u64> {
         let mut score = 0;
-        let mut scalar = 1;
+        for seat in 0..self.seats.len() {
+            score += self.update_seat(seat, &audio, soft_drop_factors.get(seat).cloned().unwrap_or(1))?;
+        }
 
-        if self.turns % (30 - (self.cleared / 3)) as i64 == 0 {
-            for i in 0..self.tiles.len() {
-                if (0..4).any(|j| self.tiles[i][j]) {
-                    audio.send(Sound::End).expect("send this pls :)");
-                    return None;
-                }
+        self.turns += 1;
+
+        if let Some(frames) = self.roll {
+            self.roll = Some(frames.saturating_sub(1));
+        }
+
+        // tick once a second through the last few seconds of a timed game
+        if let Goal::Time(ms) = self.goal {
+            let left = (ms * FRAMES_PER_SECOND / 1000).saturating_sub(self.turns as u64);
+            if left > 0 && left * 1000 <= WARNING_MS * FRAMES_PER_SECOND && left.is_multiple_of(FRAMES_PER_SECOND) {
+                audio.send(Sound::Warning).expect("warning should send");
             }
+        }
 
-            match &self.active {
-                Some(piece) => {
-                    if let Some(p) = piece.go_down(&self.tiles) {
-                        self.active = Some(p)
-                    } else {
-                        if piece.is_stationary() {
-                            for p in piece.get_tiles() {
-                                self.tiles[p.x() as usize][p.y() as usize] = true;
-                            }
-                            self.active = None;
-                            audio.send(Sound::Ground).expect("you should always send");
-                        } else {
-                            self.active = Some(piece.set_stationary(true));
+        Some(score)
+    }
+
+    fn update_seat(&mut self, seat: usize, audio: &SyncSender<Sound>, soft_drop_factor: u64) -> Option<u64> {
+        let timing = self.speed.timing(self.level);
+
+        match self.seats[seat].active.clone() {
+            None => {
+                self.seats[seat].spawn_delay += 1;
+                if self.seats[seat].spawn_delay >= timing.are && !self.spawn_blocked(seat) {
+                    // zen makes room by eating into the bottom of the stack instead of topping out
+                    if let Speed::Zen { .. } = self.speed {
+                        while self.board.occupied_above(DANGER_ROWS) {
+                            self.board.remove_bottom_row();
                         }
                     }
+                    if self.board.occupied_above(DANGER_ROWS) || !self.spawn(seat) {
+                        audio.send(Sound::End).expect("send this pls :)");
+                        return None;
+                    }
+                }
+                Some(0)
+            }
+            Some(mut piece) => {
+                let board = self.obstacles(seat);
+                let current = &mut self.seats[seat];
+                // gravity builds up in fractions of a row, so 20G drops the piece to the floor in a single frame
+                current.fall += if soft_drop_factor > 1 { timing.soft_drop(soft_drop_factor) } else { timing.gravity };
+                while current.fall >= G {
+                    current.fall -= G;
+                    match piece.go_down(&board) {
+                        Some(p) => {
+                            piece = p;
+                            current.last_rotated = false;
+                            current.lock_timer = 0;
+                            current.lock_resets = 0;
+                        }
+                        None => {
+                            current.fall = 0;
+                            break;
+                        }
+                    }
+                }
+                current.active = Some(piece.clone());
+
+                if piece.go_down(&board).is_none() {
+                    current.lock_timer += 1;
                 }
-                None => {
-                    self.active = Some(self.bag.next());
+                if current.dropped || current.lock_timer >= timing.lock_delay {
+                    return self.lock(seat, &piece, audio);
                 }
+                Some(0)
             }
+        }
+    }
 
-            for j in 0..self.tiles[0].len() {
-                if self.tiles.iter().all(|row| row[j]) {
-                    score += scalar;
-                    scalar += 1;
-                    self.cleared += 1;
-                    for i in 0..self.tiles.len() {
-                        self.tiles[i][j] = false;
-                    }
-                    audio.send(Sound::Clear).expect("should send sound");
+    // a co-op piece waits to spawn while another player's piece is in the way
+    fn spawn_blocked(&self, seat: usize) -> bool {
+        if self.seats.len() == 1 {
+            return false;
+        }
+        let board = self.obstacles(seat);
+        self.seats[seat].bag.peek_many(1).first().is_some_and(|piece| !piece.scaled(self.scale).legal(&board))
+    }
+
+    // returns false when a fixed queue has nothing left to give
+    fn spawn(&mut self, seat: usize) -> bool {
+        let current = &mut self.seats[seat];
+        current.active = match current.bag.next() {
+            Some(piece) => Some(piece.scaled(self.scale)),
+            None => return false,
+        };
+        current.reset_timers();
+        if let (Some(drill), Some(piece)) = (&mut self.drill, &current.active) {
+            drill.aim(piece, &self.board, &mut self.finesse);
+        }
+        // master levels tick up with every piece, but stop just short of each section boundary
+        if self.speed == Speed::Master && self.roll.is_none() && self.level % MASTER_SECTION_LEVELS != 99 && self.level != MASTER_MAX_LEVEL - 1 {
+            self.level += 1;
+        }
+        true
+    }
+
+    // clears and garbage can move blocks into another player's falling piece, which gets pushed up out of the way
+    fn lift_pieces(&mut self) {
+        for seat in 0..self.seats.len() {
+            let board = self.obstacles(seat);
+            if let Some(piece) = &mut self.seats[seat].active {
+                while !piece.legal(&board) && piece.anchor().y() > 0 {
+                    *piece = piece.lifted();
                 }
             }
+        }
+    }
 
-            for j in (1..self.tiles[0].len()).rev() {
-                if self.tiles.iter().all(|row| !row[j]) {
-                    for i in 0..self.tiles.len() {
-                        self.tiles[i][j] = self.tiles[i][j - 1];
-                        self.tiles[i][j - 1] = false;
+    fn lock(&mut self, seat: usize, piece: &PlayerPiece, audio: &SyncSender<Sound>) -> Option<u64> {
+        let t_spin = self.is_t_spin(seat, piece);
+        let board = self.obstacles(seat);
+        let inputs = self.finesse.optimal_inputs(piece, &board);
+        if let Some(opener) = &mut self.opener {
+            opener.check(piece);
+        }
+        for p in piece.get_tiles() {
+            self.board.place(p.x() as usize, p.y() as usize, Cell::Block, self.turns);
+        }
+        let (keys, dropped) = (self.seats[seat].keys, self.seats[seat].dropped);
+        self.seats[seat].active = None;
+        self.seats[seat].reset_timers();
+        self.pieces += 1;
+        audio.send(Sound::Ground).expect("you should always send");
+
+        if let Some(opener) = &mut self.opener {
+            opener.cleared(&self.board.full_rows());
+        }
+        let lines = self.board.clear_lines() / self.scale as usize;
+        self.cleared += lines as u64;
+        self.seats[seat].cleared += lines as u64;
+        for _ in 0..lines {
+            audio.send(Sound::Clear).expect("should send sound");
+        }
+        let perfect_clear = lines > 0 && !self.board.occupied_above(self.board.height());
+        // the fewest presses only take a hard drop when the piece was dropped, rather than left to lock
+        let optimal = match &inputs {
+            Some(inputs) => inputs.len() as u64,
+            None => tuck_keys(self.finesse.reachable(piece, &board), piece, t_spin) - 1,
+        } + dropped as u64;
+        let fault = keys > optimal;
+        let attack = self.stats.lock(lines, t_spin, perfect_clear, fault, &self.attack);
+        self.hint = if fault { inputs } else { None };
+        self.outgoing += self.incoming.cancel(attack);
+        self.achieved |= match self.goal {
+            Goal::PerfectClear => perfect_clear,
+            Goal::TSpin(goal) => t_spin && lines == goal,
+            _ => false,
+        };
+        if !self.raise_garbage() || (lines == 0 && !self.raise_incoming()) {
+            audio.send(Sound::End).expect("send this pls :)");
+            return None;
+        }
+        self.lift_pieces();
+        // every drill starts again from an empty board
+        if let Some(drill) = &mut self.drill {
+            if !drill.check(piece, fault) {
+                self.seats[seat].bag.deal_next(piece.respawned());
+            }
+            self.board = Board::new(self.board.width(), self.board.height());
+        }
+        while self.cleared >= SPLIT_LINES * (self.splits.len() as u64 + 1) {
+            self.splits.push(self.elapsed().as_millis() as u64);
+        }
+
+        let lines = lines as u64;
+        let score = match self.speed {
+            // each line in a clear is worth one more than the last
+            Speed::Guideline => {
+                self.level = self.start_level + self.cleared / LEVEL_LINES;
+                lines * (lines + 1) / 2
+            }
+            Speed::Master => {
+                let score = (self.level + lines).div_ceil(4) * lines * (self.stats.combo() + 1);
+                if self.roll.is_none() && lines > 0 {
+                    self.level = (self.level + lines).min(MASTER_MAX_LEVEL);
+                    while self.section_times.len() < (self.level / MASTER_SECTION_LEVELS) as usize {
+                        self.section_times.push(self.elapsed().as_millis() as u64);
+                    }
+                    if self.level == MASTER_MAX_LEVEL {
+                        self.section_times.push(self.elapsed().as_millis() as u64);
+                        self.board = Board::new(self.board.width(), self.board.height());
+                        self.roll = Some(ROLL_FRAMES);
                     }
                 }
+                score
             }
+            Speed::Zen { .. } => 0,
+        };
+        Some(score)
+    }
+
+    // three of the four corners around a rotated t piece have to be blocked
+    fn is_t_spin(&self, seat: usize, piece: &PlayerPiece) -> bool {
+        if !piece.is_t() || !self.seats[seat].last_rotated {
+            return false;
         }
+        let (anchor, scale) = (piece.anchor(), piece.scale());
+        [(0, 0), (2, 0), (0, 2), (2, 2)].iter()
+            .filter(|&&(x, y)| {
+                let (x, y) = (anchor.x() + x * scale, anchor.y() + y * scale);
+                y >= 0 && !self.board.is_free(x, y)
+            })
+            .count() >= 3
+    }
+}
 
-        self.turns += 1;
+impl Default for GameState {
+    fn default() -> Self {
+        GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard())
+    }
+}
 
-        Some(score)
+impl Default for Seat {
+    fn default() -> Self {
+        Seat::new(PieceBag::new(&PieceSet::standard()))
+    }
+}
+
+impl Seat {
+    fn new(bag: PieceBag) -> Seat {
+        Seat {
+            active: None,
+            bag,
+            cleared: 0,
+            spawn_delay: 0,
+            lock_timer: 0,
+            lock_resets: 0,
+            fall: 0,
+            dropped: false,
+            last_rotated: false,
+            keys: 0,
+        }
+    }
+
+    fn reset_timers(&mut self) {
+        self.spawn_delay = 0;
+        self.lock_timer = 0;
+        self.lock_resets = 0;
+        self.fall = 0;
+        self.dropped = false;
+        self.last_rotated = false;
+        self.keys = 0;
+    }
+}
+
+// spots a hard drop can't reach are held to the shortest path the search finds, soft drops and all;
+// a t-spin has to be matched by a path that spins the piece in too
+fn tuck_keys(reachable: &[Placement], piece: &PlayerPiece, spun: bool) -> u64 {
+    let target = piece.get_tiles();
+    reachable.iter()
+        .filter(|placement| placement.spun || !spun)
+        .filter(|placement| placement.piece.get_tiles().iter().all(|t| target.contains(t)))
+        .map(|placement| placement.keys())
+        .min()
+        .unwrap_or(u64::MAX)
+}
+
+fn blend(from: Color, to: Color, t: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t) as u8;
+    Color::RGB(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use super::*;
+    use crate::lib::{Controls, HandlingSettings};
+
+    fn coop() -> GameState {
+        let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard());
+        game_state.set_players(2, 20);
+        game_state
+    }
+
+    #[test]
+    fn coop_seats_are_dealt_from_the_game_seed() {
+        let dealt = |seed: u64| {
+            let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard());
+            game_state.reseed(seed);
+            game_state.set_players(2, 20);
+            assert_eq!(game_state.seed(), seed);
+            game_state.seats.iter()
+                .map(|seat| seat.bag.peek_many(14).iter().map(|p| p.name()).collect::<String>())
+                .collect::<Vec<_>>()
+        };
+        let first = dealt(7);
+        assert_eq!(first, dealt(7));
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn a_partners_presses_dont_count_against_the_other_seat() {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut game_state = coop();
+        while game_state.position(1, 0).is_none() {
+            game_state.update(audio.clone(), 1);
+        }
+        for _ in 0..3 {
+            game_state.press_as(1, Action::MoveLeft);
+            game_state.press_as(1, Action::MoveRight);
+        }
+        game_state.press_as(0, Action::HardDrop);
+        game_state.update(audio.clone(), 1);
+        assert_eq!(game_state.pieces(), 1);
+        assert_eq!(game_state.stats().finesse_faults, 0);
+        assert_eq!(game_state.stats().keys, 7);
+
+        // the partner's wiggling is still theirs to answer for when their piece locks
+        game_state.press_as(1, Action::HardDrop);
+        game_state.update(audio, 1);
+        assert_eq!(game_state.pieces(), 2);
+        assert_eq!(game_state.stats().finesse_faults, 1);
+    }
+
+    #[test]
+    fn instant_auto_shift_crosses_a_wide_board() {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut game_state = coop();
+        while game_state.position(1, 0).is_none() {
+            game_state.update(audio.clone(), 1);
+        }
+        // the first seat's piece goes out of the way, so the second can shift all the way to the left wall
+        game_state.press_as(0, Action::HardDrop);
+        game_state.update(audio, 1);
+
+        let mut controls = Controls::new();
+        controls.press(Action::MoveLeft);
+        let handling = HandlingSettings { das: 1, arr: 0, sdf: 20 };
+        for action in controls.tick(&handling, game_state.board_width()) {
+            game_state.apply_as(1, action);
+        }
+        let left = game_state.seats[1].active.as_ref().unwrap().get_tiles().iter().map(|t| t.x()).min();
+        assert_eq!(left, Some(0));
+    }
+
+        #[test]
+    fn holding_starts_the_count_again() {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard());
+        while game_state.position(0, 0).is_none() {
+            game_state.update(audio.clone(), 1);
+        }
+        game_state.press(Action::MoveLeft);
+        game_state.press(Action::MoveRight);
+        game_state.press(Action::Hold);
+        game_state.press(Action::HardDrop);
+        game_state.update(audio, 1);
+        assert_eq!(game_state.stats().finesse_faults, 0);
+        assert_eq!(game_state.stats().keys, 4);
+    }
+
+    #[test]
+    fn soft_drop_falls_without_gravity() {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut game_state = GameState::new(Goal::None, Speed::Zen { gravity: false }, 1, &PieceSet::standard());
+        while game_state.position(0, 0).is_none() {
+            game_state.update(audio.clone(), 1);
+        }
+        let top = game_state.seats[0].active.as_ref().unwrap().anchor().y();
+        for _ in 0..30 {
+            game_state.update(audio.clone(), 1);
+        }
+        assert_eq!(game_state.seats[0].active.as_ref().unwrap().anchor().y(), top);
+
+        for _ in 0..30 {
+            game_state.update(audio.clone(), 20);
+        }
+        assert!(game_state.seats[0].active.as_ref().unwrap().anchor().y() > top + 1);
+    }
+
+    fn with_queue(queue: &str) -> GameState {
+        let pieces = PieceSet::standard();
+        let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &pieces);
+        game_state.set_puzzle(Goal::None, &pieces, queue.chars().filter_map(|c| pieces.find(c)).collect(), true);
+        game_state
+    }
+
+    // presses the keys and lets the piece fall and lock by itself
+    fn let_lock(game_state: &mut GameState, actions: &[Action]) {
+        let (audio, _sounds) = sync_channel(1000);
+        while game_state.position(0, 0).is_none() {
+            game_state.update(audio.clone(), 1);
+        }
+        for &action in actions {
+            game_state.press(action);
+        }
+        let pieces = game_state.pieces();
+        while game_state.pieces() == pieces {
+            game_state.update(audio.clone(), 40);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a_piece_left_to_lock_needs_no_drop_key() {
+        // three lefts, where das left and a right would do
+        let mut game_state = with_queue("OO");
+        let_lock(&mut game_state, &[Action::MoveLeft, Action::MoveLeft, Action::MoveLeft]);
+        assert_eq!(game_state.stats().finesse_faults, 1);
+
+        let mut game_state = with_queue("OO");
+        let_lock(&mut game_state, &[Action::MoveLeft]);
+        assert_eq!(game_state.stats().finesse_faults, 0);
+    }
+
+    #[test]
+    fn a_held_soft_drop_tucks_without_gravity_at_no_cost() {
+        // the finesse trainer's speed, with an overhang over the left of the floor to tuck an o under
+        let (audio, _sounds) = sync_channel(1000);
+        let pieces = PieceSet::standard();
+        let mut game_state = GameState::new(Goal::None, Speed::Zen { gravity: false }, 1, &pieces);
+        game_state.set_puzzle(Goal::None, &pieces, "OO".chars().filter_map(|c| pieces.find(c)).collect(), true);
+        game_state.set_rows(&["XXX_______".to_string(), "__________".to_string(), "__________".to_string()]).unwrap();
+        while game_state.position(0, 0).is_none() {
+            game_state.update(audio.clone(), 1);
+        }
+
+        game_state.press(Action::SoftDrop);
+        while game_state.position(0, 0).unwrap().piece.go_down(&game_state.board).is_some() {
+            game_state.update(audio.clone(), 20);
+        }
+        for _ in 0..4 {
+            game_state.press(Action::MoveLeft);
+        }
+        game_state.press(Action::HardDrop);
+        game_state.update(audio, 1);
+        assert_eq!(game_state.pieces(), 1);
+        assert_eq!(game_state.board.get(0, 19), Cell::Block);
+        assert_eq!(game_state.stats().finesse_faults, 0);
+    }
+
+    #[test]
+    fn a_timed_game_lasts_its_time_in_frames() {
+        let (audio, sounds) = sync_channel(1000);
+        let mut game_state = GameState::new(Goal::Time(12_000), Speed::Guideline, 1, &PieceSet::standard());
+        let mut warnings = vec![];
+        while !game_state.finished() {
+            game_state.update(audio.clone(), 1);
+            if sounds.try_iter().filter(|s| matches!(s, Sound::Warning)).count() > 0 {
+                warnings.push(game_state.remaining().unwrap());
+            }
+        }
+        assert_eq!(game_state.turns, 12 * 60);
+        assert_eq!(game_state.elapsed(), Duration::from_secs(12));
+        assert_eq!(game_state.remaining(), Some(Duration::from_secs(0)));
+        // a tick for each of the last ten whole seconds
+        assert_eq!(warnings, (1..=10).rev().map(Duration::from_secs).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+
+use rand::Rng;
+use rand::seq::index::sample;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use crate::lib::Cell;
+
+// garbage draws from its own stream of the round seed, so its holes don't follow the bag's shuffles
+const STREAM: u64 = 0x0067_6172_6261_6765;
+
+// lines sent for each clear, indexed by lines cleared (or combo count); the last entry covers anything bigger
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttackTable {
+    pub clears: Vec<u64>,
+    pub t_spins: Vec<u64>,
+    pub back_to_back: u64,
+    pub combo: Vec<u64>,
+    pub perfect_clear: u64,
+}
+
+// messy holes move between rows messiness percent of the time, clean ones only between attacks,
+// and cheese holes on every row
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum HolePattern {
+    Messy,
+    Clean,
+    Cheese,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GarbageGenerator {
+    holes: usize,
+    messiness: u32,
+    pattern: HolePattern,
+    columns: Vec<usize>,
+    rng: Pcg32,
+}
+
+// incoming attacks in the order they arrived, each with the frame it starts to rise on
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct GarbageQueue {
+    delay: i64,
+    pending: VecDeque<(u64, i64)>,
+}
+
+impl AttackTable {
+    pub fn attack(&self, lines: usize, t_spin: bool, back_to_back: bool, combo: u64, perfect_clear: bool) -> u64 {
+        if lines == 0 {
+            return 0;
+        }
+        let mut attack = lookup(if t_spin { &self.t_spins } else { &self.clears }, lines - 1);
+        if back_to_back {
+            attack += self.back_to_back;
+        }
+        attack += lookup(&self.combo, combo as usize);
+        if perfect_clear {
+            attack += self.perfect_clear;
+        }
+        attack
+    }
+}
+
+impl Default for AttackTable {
+    fn default() -> Self {
+        AttackTable {
+            clears: vec![0, 1, 2, 4],
+            t_spins: vec![2, 4, 6],
+            back_to_back: 1,
+            combo: vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5],
+            perfect_clear: 10,
+        }
+    }
+}
+
+impl GarbageGenerator {
+    pub fn new(holes: usize, messiness: u32, pattern: HolePattern, seed: u64) -> GarbageGenerator {
+        GarbageGenerator {
+            holes,
+            messiness,
+            pattern,
+            columns: vec![],
+            rng: Pcg32::new(seed, STREAM),
+        }
+    }
+
+    // generators reseeded alike make the same holes, so versus players dig out the same garbage
+    pub fn reseed(&mut self, seed: u64) {
+        self.columns.clear();
+        self.rng = Pcg32::new(seed, STREAM);
+    }
+
+    // the start of a new attack, which moves clean holes
+    pub fn new_batch(&mut self) {
+        if self.pattern == HolePattern::Clean {
+            self.columns.clear();
+        }
+    }
+
+    pub fn next_row(&mut self, width: usize) -> Vec<Cell> {
+        let holes = self.holes.max(1).min(width - 1);
+        let moves = match self.pattern {
+            HolePattern::Messy => self.rng.gen_range(0, 100) < self.messiness,
+            HolePattern::Clean => false,
+            HolePattern::Cheese => true,
+        };
+        if self.columns.len() != holes || moves {
+            self.columns = sample(&mut self.rng, width, holes).into_vec();
+        }
+        (0..width)
+            .map(|x| if self.columns.contains(&x) { Cell::Empty } else { Cell::Garbage })
+            .collect()
+    }
+}
+
+impl GarbageQueue {
+    pub fn new(delay: u32) -> GarbageQueue {
+        GarbageQueue {
+            delay: delay as i64,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, rows: u64, frame: i64) {
+        if rows > 0 {
+            self.pending.push_back((rows, frame + self.delay));
+        }
+    }
+
+    // an attack cancels the oldest incoming garbage first, and whatever is left over goes to the opponent
+    pub fn cancel(&mut self, mut attack: u64) -> u64 {
+        while attack > 0 {
+            match self.pending.front_mut() {
+                Some((rows, _)) if *rows > attack => {
+                    *rows -= attack;
+                    return 0;
+                }
+                Some(&mut (rows, _)) => {
+                    attack -= rows;
+                    self.pending.pop_front();
+                }
+                None => break,
+            }
+        }
+        attack
+    }
+
+    // removes and returns every attack whose delay is over
+    pub fn take_ready(&mut self, frame: i64) -> Vec<u64> {
+        let mut ready = vec![];
+        while let Some(&(rows, arrives)) = self.pending.front() {
+            if arrives > frame {
+                break;
+            }
+            ready.push(rows);
+            self.pending.pop_front();
+        }
+        ready
+    }
+
+    pub fn total(&self) -> u64 {
+        self.pending.iter().map(|&(rows, _)| rows).sum()
+    }
+
+    pub fn ready(&self, frame: i64) -> u64 {
+        self.pending.iter().filter(|&&(_, arrives)| arrives <= frame).map(|&(rows, _)| rows).sum()
+    }
+}
+
+fn lookup(table: &[u64], index: usize) -> u64 {
+    table.get(index).or_else(|| table.last()).cloned().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holes(row: &[Cell]) -> Vec<usize> {
+        row.iter().enumerate().filter(|(_, &c)| c == Cell::Empty).map(|(x, _)| x).collect()
+    }
+
+    #[test]
+    fn attacks_come_from_the_table() {
+        let table = AttackTable::default();
+        assert_eq!(table.attack(0, false, true, 5, false), 0);
+        assert_eq!(table.attack(1, false, false, 0, false), 0);
+        assert_eq!(table.attack(4, false, false, 0, false), 4);
+        assert_eq!(table.attack(2, true, true, 0, false), 5);
+        assert_eq!(table.attack(1, false, false, 2, false), 1);
+        assert_eq!(table.attack(4, false, false, 0, true), 14);
+    }
+
+    #[test]
+    fn the_last_entry_covers_anything_bigger() {
+        let table = AttackTable::default();
+        assert_eq!(table.attack(5, false, false, 0, false), 4);
+        assert_eq!(table.attack(4, true, false, 0, false), 6);
+        assert_eq!(table.attack(1, false, false, 30, false), 5);
+        let empty = AttackTable { clears: vec![], t_spins: vec![], back_to_back: 0, combo: vec![], perfect_clear: 0 };
+        assert_eq!(empty.attack(4, false, false, 3, false), 0);
+    }
+
+    #[test]
+    fn attacks_cancel_the_oldest_garbage_first() {
+        let mut queue = GarbageQueue::new(0);
+        queue.push(2, 0);
+        queue.push(3, 1);
+        assert_eq!(queue.cancel(3), 0);
+        assert_eq!(queue.total(), 2);
+        assert_eq!(queue.take_ready(1), vec![2]);
+
+        queue.push(1, 2);
+        assert_eq!(queue.cancel(4), 3);
+        assert_eq!(queue.total(), 0);
+    }
+
+    #[test]
+    fn garbage_waits_out_its_delay() {
+        let mut queue = GarbageQueue::new(60);
+        queue.push(0, 0);
+        queue.push(2, 0);
+        queue.push(1, 30);
+        assert_eq!(queue.total(), 3);
+        assert_eq!(queue.ready(59), 0);
+        assert!(queue.take_ready(59).is_empty());
+        assert_eq!(queue.ready(60), 2);
+        assert_eq!(queue.take_ready(89), vec![2]);
+        assert_eq!(queue.take_ready(90), vec![1]);
+        assert_eq!(queue.total(), 0);
+    }
+
+    #[test]
+    fn clean_holes_move_between_attacks_only() {
+        let mut generator = GarbageGenerator::new(1, 0, HolePattern::Clean, 1);
+        let first = holes(&generator.next_row(10));
+        assert_eq!(first.len(), 1);
+        for _ in 0..10 {
+            assert_eq!(holes(&generator.next_row(10)), first);
+        }
+        let moved = (0..20).any(|_| {
+            generator.new_batch();
+            holes(&generator.next_row(10)) != first
+        });
+        assert!(moved);
+    }
+
+    #[test]
+    fn every_row_has_its_holes() {
+        for &pattern in [HolePattern::Messy, HolePattern::Clean, HolePattern::Cheese].iter() {
+            let mut generator = GarbageGenerator::new(12, 50, pattern, 2);
+            for _ in 0..20 {
+                let row = generator.next_row(10);
+                assert_eq!(row.len(), 10);
+                assert_eq!(holes(&row).len(), 9);
+            }
+        }
+    }
+
+    #[test]
+    fn generators_seeded_alike_make_the_same_holes() {
+        let mut first = GarbageGenerator::new(1, 100, HolePattern::Cheese, 3);
+        let mut second = GarbageGenerator::new(1, 100, HolePattern::Cheese, 4);
+        second.next_row(10);
+        second.reseed(3);
+        for _ in 0..20 {
+            assert_eq!(holes(&first.next_row(10)), holes(&second.next_row(10)));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+use crate::lib::HandlingSettings;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    Rotate,
+    Hold,
+}
+
+pub struct Controls {
+    shifting: Option<Action>,
+    shifted_for: u32,
+    soft_dropping: bool,
+    revealing: bool,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::Rotate,
+        Action::Hold,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::Rotate => "rotate",
+            Action::Hold => "hold",
+        }
+    }
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Controls::new()
+    }
+}
+
+impl Controls {
+    pub fn new() -> Controls {
+        Controls {
+            shifting: None,
+            shifted_for: 0,
+            soft_dropping: false,
+            revealing: false,
+        }
+    }
+
+    pub fn press(&mut self, action: Action) -> Action {
+        match action {
+            Action::MoveLeft
+            | Action::MoveRight => {
+                self.shifting = Some(action);
+                self.shifted_for = 0;
+            }
+            Action::SoftDrop => self.soft_dropping = true,
+            _ => (),
+        }
+        action
+    }
+
+    pub fn release(&mut self, action: Action) {
+        if self.shifting == Some(action) {
+            self.shifting = None;
+        }
+        if action == Action::SoftDrop {
+            self.soft_dropping = false;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.shifting = None;
+        self.soft_dropping = false;
+        self.revealing = false;
+    }
+
+    pub fn soft_dropping(&self) -> bool {
+        self.soft_dropping
+    }
+
+    pub fn reveal(&mut self, held: bool) {
+        self.revealing = held;
+    }
+
+    pub fn revealing(&self) -> bool {
+        self.revealing
+    }
+
+    // auto shift repeats the held direction once it has been held for das frames, then every arr frames;
+    // with an arr of 0 it goes the width of the board at once, which is always enough to reach a wall
+    pub fn tick(&mut self, handling: &HandlingSettings, width: usize) -> Vec<Action> {
+        let action = match self.shifting {
+            Some(action) => action,
+            None => return vec![],
+        };
+
+        self.shifted_for += 1;
+        if self.shifted_for < handling.das {
+            vec![]
+        } else if handling.arr == 0 {
+            vec![action; width]
+        } else if (self.shifted_for - handling.das).is_multiple_of(handling.arr) {
+            vec![action]
+        } else {
+            vec![]
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+const MAX_ENTRIES: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub date: NaiveDate,
+    pub score: u64,
+    pub lines: u64,
+    pub time_ms: u64,
+    pub pps: f64,
+    pub seed: u64,
+    #[serde(default)]
+    pub splits: Vec<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ranking {
+    Score,
+    Time,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Leaderboards {
+    boards: BTreeMap<String, Vec<LeaderboardEntry>>,
+}
+
+impl Leaderboards {
+    pub fn load(path: &Path) -> Result<Leaderboards, String> {
+        if !path.exists() {
+            return Ok(Leaderboards::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+
+        // written aside and moved over, so a crash part way through leaves the old scores in place
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents).map_err(|e| e.to_string())?;
+        fs::rename(&temp, path).map_err(|e| e.to_string())
+    }
+
+    pub fn entries(&self, mode: &str, ruleset: &str) -> &[LeaderboardEntry] {
+        self.boards.get(&Leaderboards::key(mode, ruleset))
+            .map(|entries| entries.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn best(&self, mode: &str, ruleset: &str) -> Option<&LeaderboardEntry> {
+        self.entries(mode, ruleset).first()
+    }
+
+    pub fn qualifies(&self, mode: &str, ruleset: &str, ranking: Ranking, entry: &LeaderboardEntry) -> bool {
+        let entries = self.entries(mode, ruleset);
+        let counts = match ranking {
+            Ranking::Score => entry.score > 0,
+            Ranking::Time => entry.time_ms > 0,
+        };
+        counts && (entries.len() < MAX_ENTRIES || entries.iter().any(|e| ranking.beats(entry, e)))
+    }
+
+    pub fn insert(&mut self, mode: &str, ruleset: &str, ranking: Ranking, entry: LeaderboardEntry) {
+        let entries = self.boards.entry(Leaderboards::key(mode, ruleset)).or_default();
+        let position = entries.iter()
+            .position(|e| ranking.beats(&entry, e))
+            .unwrap_or(entries.len());
+        entries.insert(position, entry);
+        entries.truncate(MAX_ENTRIES);
+    }
+
+    fn key(mode: &str, ruleset: &str) -> String {
+        format!("{}/{}", mode, ruleset)
+    }
+}
+
+impl Ranking {
+    fn beats(self, a: &LeaderboardEntry, b: &LeaderboardEntry) -> bool {
+        match self {
+            Ranking::Score => a.score > b.score,
+            Ranking::Time => a.time_ms < b.time_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::lib::Mode;
+
+    fn entry(name: &str, score: u64, time_ms: u64) -> LeaderboardEntry {
+        LeaderboardEntry {
+            name: name.to_string(),
+            date: NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+            score,
+            lines: 0,
+            time_ms,
+            pps: 0.0,
+            seed: 0,
+            splits: vec![],
+        }
+    }
+
+    fn names(boards: &Leaderboards, mode: &str) -> Vec<String> {
+        boards.entries(mode, "standard").iter().map(|e| e.name.clone()).collect()
+    }
+
+    #[test]
+    fn scores_rank_highest_first() {
+        let mut boards = Leaderboards::default();
+        for (name, score) in [("b", 200), ("a", 300), ("c", 100)].iter() {
+            boards.insert("marathon", "standard", Ranking::Score, entry(name, *score, 0));
+        }
+        assert_eq!(names(&boards, "marathon"), vec!["a", "b", "c"]);
+        assert_eq!(boards.best("marathon", "standard").unwrap().score, 300);
+    }
+
+    #[test]
+    fn times_rank_fastest_first_and_ties_keep_the_earlier_entry() {
+        let mut boards = Leaderboards::default();
+        for (name, time) in [("slow", 90_000), ("first", 60_000), ("second", 60_000)].iter() {
+            boards.insert("sprint", "standard", Ranking::Time, entry(name, 0, *time));
+        }
+        assert_eq!(names(&boards, "sprint"), vec!["first", "second", "slow"]);
+    }
+
+    #[test]
+    fn full_boards_only_take_better_entries() {
+        let mut boards = Leaderboards::default();
+        for score in 1..=MAX_ENTRIES as u64 {
+            boards.insert("marathon", "standard", Ranking::Score, entry("x", score * 10, 0));
+        }
+        assert!(!boards.qualifies("marathon", "standard", Ranking::Score, &entry("low", 5, 0)));
+        assert!(boards.qualifies("marathon", "standard", Ranking::Score, &entry("high", 15, 0)));
+
+        boards.insert("marathon", "standard", Ranking::Score, entry("high", 15, 0));
+        let entries = boards.entries("marathon", "standard");
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries.last().unwrap().score, 15);
+    }
+
+    #[test]
+    fn empty_results_never_qualify() {
+        let boards = Leaderboards::default();
+        assert!(!boards.qualifies("marathon", "standard", Ranking::Score, &entry("none", 0, 0)));
+        assert!(!boards.qualifies("sprint", "standard", Ranking::Time, &entry("none", 0, 0)));
+    }
+
+    #[test]
+    fn modes_and_rulesets_keep_separate_boards() {
+        let mut boards = Leaderboards::default();
+        boards.insert("marathon", "standard", Ranking::Score, entry("a", 100, 0));
+        assert!(boards.entries("marathon", "big").is_empty());
+        assert!(boards.entries("ultra", "standard").is_empty());
+    }
+
+    #[test]
+    fn stored_boards_load_back_with_nothing_left_aside() {
+        let path = env::temp_dir().join(format!("tetris-scores-{}.json", std::process::id()));
+        let mut boards = Leaderboards::default();
+        boards.insert("marathon", "standard", Ranking::Score, entry("a", 100, 0));
+        boards.store(&path).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(names(&Leaderboards::load(&path).unwrap(), "marathon"), vec!["a"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn every_mode_that_ranks_has_a_board_to_browse() {
+        let ranked = Mode::ranked();
+        assert!(ranked.contains(&Mode::Coop));
+        assert!(ranked.iter().all(|mode| mode.ranking().is_some()));
+    }
+}
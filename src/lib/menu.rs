@@ -0,0 +1,103 @@
+use sdl2::controller::Button;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+
+use crate::lib::Theme;
+use crate::lib::text::draw_text;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+pub struct Menu<T> {
+    title: String,
+    items: Vec<(String, T)>,
+    selected: usize,
+}
+
+impl MenuInput {
+    pub fn from_event(event: &Event) -> Option<MenuInput> {
+        match event {
+            Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+                Keycode::Up | Keycode::W => Some(MenuInput::Up),
+                Keycode::Down | Keycode::S => Some(MenuInput::Down),
+                Keycode::Left | Keycode::A => Some(MenuInput::Left),
+                Keycode::Right | Keycode::D => Some(MenuInput::Right),
+                Keycode::Return | Keycode::Space => Some(MenuInput::Select),
+                Keycode::Escape | Keycode::Backspace => Some(MenuInput::Back),
+                _ => None,
+            },
+            Event::ControllerButtonDown { button, .. } => match button {
+                Button::DPadUp => Some(MenuInput::Up),
+                Button::DPadDown => Some(MenuInput::Down),
+                Button::DPadLeft => Some(MenuInput::Left),
+                Button::DPadRight => Some(MenuInput::Right),
+                Button::A | Button::Start => Some(MenuInput::Select),
+                Button::B | Button::Back => Some(MenuInput::Back),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<T: Copy> Menu<T> {
+    pub fn new(title: &str, items: Vec<(String, T)>) -> Menu<T> {
+        Menu {
+            title: title.to_string(),
+            items,
+            selected: 0,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.items.len() {
+            self.selected = index;
+        }
+    }
+
+    pub fn handle(&mut self, input: MenuInput) -> Option<T> {
+        if self.items.is_empty() {
+            return None;
+        }
+        match input {
+            MenuInput::Up => self.selected = (self.selected + self.items.len() - 1) % self.items.len(),
+            MenuInput::Down => self.selected = (self.selected + 1) % self.items.len(),
+            MenuInput::Select => return Some(self.items[self.selected].1),
+            _ => (),
+        }
+        None
+    }
+
+    pub fn draw<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, theme: &Theme, x: i32, y: i32) -> Result<(), String> {
+        if !self.title.is_empty() {
+            draw_text(canvas, font, &self.title, theme.text.0, Rect::new(x, y, 24 * self.title.len() as u32, 50))?;
+        }
+        for (i, (label, _)) in self.items.iter().enumerate() {
+            draw_item(canvas, font, theme, label, x, y + 80 + 45 * i as i32, i == self.selected)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn draw_item<'a>(canvas: &mut WindowCanvas, font: &Font<'a, 'static>, theme: &Theme, label: &str, x: i32, y: i32, focused: bool) -> Result<(), String> {
+    let width = 16 * label.chars().count() as u32;
+    if focused {
+        canvas.set_draw_color(theme.grid.0);
+        canvas.fill_rect(Rect::new(x - 8, y - 4, width + 16, 38))?;
+    }
+    draw_text(canvas, font, label, theme.text.0, Rect::new(x, y, width, 30))
+}
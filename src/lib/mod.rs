@@ -1,9 +1,91 @@
 pub use app_state::AppState;
 pub use app_state::Sound;
+pub use board::Board;
+pub use bot::Bot;
+pub use bot::BotWeights;
+pub use bot::Plan;
+pub use bot::Position;
+pub use board::Cell;
+pub use finesse::Drill;
+pub use finesse::FinesseCache;
+pub use finesse::FinesseInput;
+pub use fumen::Field;
+pub use fumen::Fumen;
+pub use fumen::FumenPage;
+pub use fumen::FumenPiece;
 pub use game_state::GameState;
+pub use game_state::Goal;
+pub use garbage::AttackTable;
+pub use garbage::GarbageGenerator;
+pub use garbage::GarbageQueue;
+pub use garbage::HolePattern;
+pub use input::Action;
+pub use input::Controls;
+pub use leaderboard::LeaderboardEntry;
+pub use leaderboard::Leaderboards;
+pub use leaderboard::Ranking;
+pub use menu::Menu;
+pub use menu::MenuInput;
+pub use mode::MAX_START_LEVEL;
+pub use mode::Mode;
+pub use opener::Opener;
+pub use opener::OpenerTemplate;
+pub use options::OptionsMenu;
+pub use options::OptionsOutcome;
+pub use piece_set::PieceSet;
 pub use player_piece::PieceBag;
 pub use player_piece::PlayerPiece;
+pub use puzzle::Puzzle;
+pub use puzzle::PuzzlePack;
+pub use puzzle::PuzzleProgress;
+pub use replay::Replay;
+pub use replay::ReplayInput;
+pub use replay::ReplayPlayer;
+pub use replay::ReplayRecorder;
+pub use rules::G;
+pub use rules::MASTER_MAX_LEVEL;
+pub use rules::MASTER_SECTION_LEVELS;
+pub use rules::master_grade;
+pub use rules::Speed;
+pub use save::SaveFile;
+pub use settings::Binding;
+pub use settings::BotSettings;
+pub use settings::HandlingSettings;
+pub use settings::MAX_BOT_DELAY;
+pub use settings::MAX_FIRST_TO;
+pub use settings::MAX_PREVIEW_COUNT;
+pub use settings::PAUSE_KEYS;
+pub use settings::Settings;
+pub use settings::SETTINGS_PATH;
+pub use settings::Theme;
+pub use settings::Visibility;
+pub use stats::StatField;
+pub use stats::Stats;
+pub use tbp::ExternalBot;
+pub use versus::Versus;
 
 mod app_state;
+mod board;
+mod bot;
+mod finesse;
+mod fumen;
 mod game_state;
-mod player_piece;
\ No newline at end of file
+mod garbage;
+mod input;
+mod leaderboard;
+mod menu;
+mod mode;
+mod opener;
+mod options;
+mod piece_set;
+mod player_piece;
+mod puzzle;
+mod replay;
+mod rules;
+mod save;
+mod search;
+mod settings;
+mod stats;
+mod tbp;
+mod text;
+mod versus;
\ No newline at end of file
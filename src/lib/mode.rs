@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Goal, Ranking, Speed};
+
+pub const MAX_START_LEVEL: u64 = 15;
+const BIG_LINES: u64 = 150;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Marathon { start_level: u64, lines: Option<u64> },
+    Sprint(u64),
+    Ultra(u64),
+    Dig(u64),
+    Master,
+    Big,
+    Zen { gravity: bool },
+    Puzzle,
+    Versus,
+    Coop,
+    Finesse,
+    Opener,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 14] = [
+        Mode::Marathon { start_level: 1, lines: Some(150) },
+        Mode::Marathon { start_level: 1, lines: None },
+        Mode::Sprint(40),
+        Mode::Sprint(20),
+        Mode::Sprint(100),
+        Mode::Ultra(120),
+        Mode::Dig(10),
+        Mode::Dig(18),
+        Mode::Dig(100),
+        Mode::Master,
+        Mode::Big,
+        Mode::Zen { gravity: true },
+        Mode::Zen { gravity: false },
+        Mode::Finesse,
+    ];
+    // modes with their own way in rather than a place in the mode list
+    const ELSEWHERE: [Mode; 4] = [Mode::Puzzle, Mode::Versus, Mode::Coop, Mode::Opener];
+
+    // every mode that keeps a leaderboard, in the order the high scores screen goes through them
+    pub fn ranked() -> Vec<Mode> {
+        Mode::ALL.iter()
+            .chain(Mode::ELSEWHERE.iter())
+            .cloned()
+            .filter(|mode| mode.ranking().is_some())
+            .collect()
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Mode::Marathon { lines: Some(lines), .. } => format!("Marathon {}", lines),
+            Mode::Marathon { lines: None, .. } => "Marathon endless".to_string(),
+            Mode::Sprint(lines) => format!("Sprint {}", lines),
+            Mode::Ultra(seconds) => format!("Ultra {}:{:02}", seconds / 60, seconds % 60),
+            Mode::Dig(rows) => format!("Dig {}", rows),
+            Mode::Master => "Master".to_string(),
+            Mode::Big => "Big".to_string(),
+            Mode::Zen { gravity: true } => "Zen".to_string(),
+            Mode::Zen { gravity: false } => "Zen without gravity".to_string(),
+            Mode::Puzzle => "Puzzle".to_string(),
+            Mode::Versus => "Versus".to_string(),
+            Mode::Coop => "Co-op".to_string(),
+            Mode::Finesse => "Finesse trainer".to_string(),
+            Mode::Opener => "Opener trainer".to_string(),
+        }
+    }
+
+    // a leaderboard for each variant: marathon keys on its line goal, so 150 lines and endless rank apart,
+    // while the start level is picked within a variant and every start level ranks together
+    pub fn key(self) -> String {
+        match self {
+            Mode::Marathon { lines: Some(lines), .. } => format!("marathon{}", lines),
+            Mode::Marathon { lines: None, .. } => "marathon_endless".to_string(),
+            Mode::Sprint(lines) => format!("sprint{}", lines),
+            Mode::Ultra(seconds) => format!("ultra{}", seconds),
+            Mode::Dig(rows) => format!("dig{}", rows),
+            Mode::Master => "master".to_string(),
+            Mode::Big => "big".to_string(),
+            Mode::Zen { gravity: true } => "zen".to_string(),
+            Mode::Zen { gravity: false } => "zen_no_gravity".to_string(),
+            Mode::Puzzle => "puzzle".to_string(),
+            Mode::Versus => "versus".to_string(),
+            Mode::Coop => "coop".to_string(),
+            Mode::Finesse => "finesse".to_string(),
+            Mode::Opener => "opener".to_string(),
+        }
+    }
+
+    pub fn goal(self) -> Goal {
+        match self {
+            Mode::Marathon { lines: Some(lines), .. } => Goal::Lines(lines),
+            Mode::Marathon { lines: None, .. } => Goal::None,
+            Mode::Sprint(lines) => Goal::Lines(lines),
+            Mode::Ultra(seconds) => Goal::Time(seconds * 1000),
+            Mode::Dig(_) => Goal::Garbage,
+            Mode::Master => Goal::Roll,
+            Mode::Big => Goal::Lines(BIG_LINES),
+            Mode::Zen { .. }
+            | Mode::Puzzle
+            | Mode::Versus
+            | Mode::Coop
+            | Mode::Finesse
+            | Mode::Opener => Goal::None,
+        }
+    }
+
+    pub fn start_level(self) -> u64 {
+        match self {
+            Mode::Marathon { start_level, .. } => start_level,
+            Mode::Master => 0,
+            _ => 1,
+        }
+    }
+
+    pub fn speed(self) -> Speed {
+        match self {
+            Mode::Master => Speed::Master,
+            Mode::Zen { gravity } => Speed::Zen { gravity },
+            // no gravity in the trainer, so there's time to think about each drill; a held soft drop still
+            // falls, so a tuck costs the one key the finesse count expects
+            Mode::Finesse => Speed::Zen { gravity: false },
+            _ => Speed::Guideline,
+        }
+    }
+
+    pub fn with_start_level(self, level: u64) -> Mode {
+        match self {
+            Mode::Marathon { lines, .. } => Mode::Marathon { start_level: level, lines },
+            _ => self,
+        }
+    }
+
+    // zen, puzzles, versus and the trainers keep no score, so they never touch the leaderboards
+    pub fn ranking(self) -> Option<Ranking> {
+        match self {
+            Mode::Marathon { .. }
+            | Mode::Ultra(_)
+            | Mode::Master
+            | Mode::Big
+            | Mode::Coop => Some(Ranking::Score),
+            Mode::Sprint(_)
+            | Mode::Dig(_) => Some(Ranking::Time),
+            Mode::Zen { .. }
+            | Mode::Puzzle
+            | Mode::Versus
+            | Mode::Finesse
+            | Mode::Opener => None,
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Board, PieceSet, PlayerPiece};
+use crate::lib::finesse;
+
+#[derive(Deserialize)]
+pub struct Opener {
+    pub name: String,
+    branches: Vec<Branch>,
+}
+
+// rows are drawn top to bottom with the last one on the floor, each piece written with its own
+// letter (lowercase for pieces from the second bag) and '_' for cells the opener leaves empty, as if
+// no lines had cleared along the way; a branch is only built when its first bag pieces are dealt in
+// the order given by when, and one without a when always matches
+#[derive(Deserialize)]
+struct Branch {
+    #[serde(default)]
+    when: String,
+    rows: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Placement {
+    piece: char,
+    cells: Vec<(i32, i32)>,
+    placed: bool,
+}
+
+// the branch picked for one game, tracking which of its pieces are down and which locks strayed from it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OpenerTemplate {
+    name: String,
+    placements: Vec<Placement>,
+    deviations: u64,
+    strayed: Vec<(i32, i32)>,
+}
+
+impl Opener {
+    pub fn load_all(dir: &Path) -> Vec<Opener> {
+        let mut paths: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return vec![],
+        };
+        paths.sort();
+
+        paths.iter()
+            .filter(|path| path.extension().is_some_and(|e| e == "toml"))
+            .filter_map(|path| match Opener::load(path) {
+                Ok(opener) => Some(opener),
+                Err(e) => {
+                    eprintln!("skipping opener {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn load(path: &Path) -> Result<Opener, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let opener: Opener = toml::from_str(&contents).map_err(|e| e.to_string())?;
+        if opener.branches.is_empty() {
+            return Err("there are no branches".to_string());
+        }
+        let board = Board::new(10, 20);
+        for branch in opener.branches.iter() {
+            branch.placements(false, &board).map_err(|e| format!("{}: {}", opener.name, e))?;
+        }
+        Ok(opener)
+    }
+
+    // upcoming is the first bag, in the order it will be dealt
+    pub fn template(&self, mirrored: bool, upcoming: &[char], board: &Board) -> Result<OpenerTemplate, String> {
+        let branch = self.branches.iter()
+            .find(|branch| branch.matches(upcoming))
+            .ok_or_else(|| format!("no branch of {} fits this bag", self.name))?;
+        Ok(OpenerTemplate {
+            name: if mirrored { format!("{} mirrored", self.name) } else { self.name.clone() },
+            placements: branch.placements(mirrored, board)?,
+            deviations: 0,
+            strayed: vec![],
+        })
+    }
+}
+
+impl Branch {
+    fn matches(&self, upcoming: &[char]) -> bool {
+        let positions: Vec<Option<usize>> = self.when.chars().map(|name| upcoming.iter().position(|&c| c == name)).collect();
+        positions.iter().all(|p| p.is_some()) && positions.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    // mirroring flips the rows and swaps each piece for its mirror image
+    fn placements(&self, mirrored: bool, board: &Board) -> Result<Vec<Placement>, String> {
+        let width = self.rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+        if width > board.width() || self.rows.len() > board.height() {
+            return Err(format!("the rows don't fit on a {}x{} board", board.width(), board.height()));
+        }
+
+        let mut cells: BTreeMap<char, Vec<(i32, i32)>> = BTreeMap::new();
+        let top = (board.height() - self.rows.len()) as i32;
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate().filter(|&(_, c)| c != '_') {
+                let (c, x) = if mirrored { (mirror(c), (board.width() - 1 - x) as i32) } else { (c, x as i32) };
+                cells.entry(c).or_default().push((x, top + y as i32));
+            }
+        }
+
+        let pieces = PieceSet::standard();
+        cells.into_iter()
+            .map(|(name, mut cells)| {
+                let piece = pieces.find(name.to_ascii_uppercase()).ok_or_else(|| format!("there is no {} piece", name))?;
+                cells.sort();
+                if !shapes(&piece).contains(&normalized(&cells)) {
+                    return Err(format!("the {} cells don't make a {} piece", name, name));
+                }
+                Ok(Placement { piece: piece.name(), cells, placed: false })
+            })
+            .collect()
+    }
+}
+
+impl OpenerTemplate {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn placed(&self) -> usize {
+        self.placements.iter().filter(|p| p.placed).count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.placements.len()
+    }
+
+    pub fn complete(&self) -> bool {
+        self.placements.iter().all(|p| p.placed)
+    }
+
+    pub fn deviations(&self) -> u64 {
+        self.deviations
+    }
+
+    // the cells of the last piece, when it went somewhere the template doesn't have it
+    pub fn strayed(&self) -> &[(i32, i32)] {
+        &self.strayed
+    }
+
+    // the cells of every piece still to place
+    pub fn pending(&self) -> impl Iterator<Item = &(i32, i32)> {
+        self.placements.iter().filter(|p| !p.placed).flat_map(|p| p.cells.iter())
+    }
+
+    // the pieces still to place move down with the rows under them, so a template can carry on past a clear
+    pub fn cleared(&mut self, rows: &[usize]) {
+        let drop = |&(x, y): &(i32, i32)| (x, y + rows.iter().filter(|&&row| row as i32 > y).count() as i32);
+        for placement in self.placements.iter_mut().filter(|p| !p.placed) {
+            placement.cells = placement.cells.iter().map(drop).collect();
+        }
+        self.strayed = self.strayed.iter().filter(|&&(_, y)| !rows.contains(&(y as usize))).map(drop).collect();
+    }
+
+    // a piece has to lock exactly where the template wants one of its kind, or it counts as a deviation
+    pub fn check(&mut self, piece: &PlayerPiece) {
+        let mut cells: Vec<(i32, i32)> = piece.get_tiles().iter().map(|p| (p.x(), p.y())).collect();
+        cells.sort();
+        match self.placements.iter_mut().find(|p| !p.placed && p.piece == piece.name() && p.cells == cells) {
+            Some(placement) => {
+                placement.placed = true;
+                self.strayed.clear();
+            }
+            None => {
+                self.deviations += 1;
+                self.strayed = cells;
+            }
+        }
+    }
+}
+
+fn mirror(name: char) -> char {
+    let mirrored = match name.to_ascii_uppercase() {
+        'L' => 'J',
+        'J' => 'L',
+        'S' => 'Z',
+        'Z' => 'S',
+        c => c,
+    };
+    if name.is_ascii_lowercase() { mirrored.to_ascii_lowercase() } else { mirrored }
+}
+
+// every orientation of the piece, taken from where it can land on an empty board
+fn shapes(piece: &PlayerPiece) -> Vec<Vec<(i32, i32)>> {
+    let board = Board::new(10, 20);
+    finesse::placements(piece, &board).iter()
+        .map(|(placed, _)| normalized(&placed.get_tiles().iter().map(|p| (p.x(), p.y())).collect::<Vec<_>>()))
+        .collect()
+}
+
+fn normalized(cells: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let left = cells.iter().map(|c| c.0).min().unwrap_or(0);
+    let top = cells.iter().map(|c| c.1).min().unwrap_or(0);
+    let mut cells: Vec<(i32, i32)> = cells.iter().map(|&(x, y)| (x - left, y - top)).collect();
+    cells.sort();
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::lib::{Cell, search};
+
+    fn template(branch: &Branch, mirrored: bool, board: &Board) -> OpenerTemplate {
+        OpenerTemplate { name: String::new(), placements: branch.placements(mirrored, board).unwrap(), deviations: 0, strayed: vec![] }
+    }
+
+    // whether the pieces still to place can all go down in some order, each reaching its cells from its
+    // spawn the way a player could, with the rows cleared along the way taken out
+    fn buildable(template: &OpenerTemplate, board: &Board, failed: &mut HashSet<Vec<bool>>) -> bool {
+        if template.complete() {
+            return true;
+        }
+        let placed: Vec<bool> = template.placements.iter().map(|p| p.placed).collect();
+        if failed.contains(&placed) {
+            return false;
+        }
+        let pieces = PieceSet::standard();
+        let cells = |piece: &PlayerPiece| {
+            let mut cells: Vec<(i32, i32)> = piece.get_tiles().iter().map(|t| (t.x(), t.y())).collect();
+            cells.sort();
+            cells
+        };
+        let built = template.placements.iter().enumerate().filter(|(_, p)| !p.placed).any(|(i, placement)| {
+            // most pieces just drop into place, so the slower search that takes in tucks and spins is left till last
+            let piece = pieces.find(placement.piece).unwrap();
+            let reached = finesse::placements(&piece, board).iter().any(|(p, _)| cells(p) == placement.cells)
+                || search::reachable(&piece, board).iter().any(|p| cells(&p.piece) == placement.cells);
+            if !reached {
+                return false;
+            }
+            let (mut template, mut board) = (template.clone(), board.clone());
+            for &(x, y) in placement.cells.iter() {
+                board.place(x as usize, y as usize, Cell::Block, 0);
+            }
+            template.placements[i].placed = true;
+            template.cleared(&board.full_rows());
+            board.clear_lines();
+            buildable(&template, &board, failed)
+        });
+        if !built {
+            failed.insert(placed);
+        }
+        built
+    }
+
+    #[test]
+    fn shipped_openers_can_be_built() {
+        let openers = Opener::load_all(Path::new("openers"));
+        assert!(openers.len() >= 5);
+        let board = Board::new(10, 20);
+        for opener in openers.iter() {
+            for (i, branch) in opener.branches.iter().enumerate() {
+                for &mirrored in [false, true].iter() {
+                    let template = template(branch, mirrored, &board);
+                    assert!(buildable(&template, &board, &mut HashSet::new()), "{} branch {} mirrored {} can't be built", opener.name, i, mirrored);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn branches_follow_the_bag_order() {
+        let opener = Opener::load(Path::new("openers/pco.toml")).unwrap();
+        let board = Board::new(10, 20);
+        let j_first = opener.template(false, &['J', 'I', 'S', 'O', 'T', 'L', 'Z'], &board).unwrap();
+        let s_first = opener.template(false, &['S', 'I', 'J', 'O', 'T', 'L', 'Z'], &board).unwrap();
+        assert_eq!((j_first.total(), s_first.total()), (10, 10));
+        assert_ne!(j_first.pending().collect::<Vec<_>>(), s_first.pending().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn mirroring_keeps_the_bag_a_piece_comes_from() {
+        assert_eq!(mirror('L'), 'J');
+        assert_eq!(mirror('s'), 'z');
+        assert_eq!(mirror('o'), 'o');
+        let branch = Branch { when: String::new(), rows: vec!["S__s______".to_string(), "SS_ss_____".to_string(), "_S__s_____".to_string()] };
+        let board = Board::new(10, 20);
+        let mirrored = branch.placements(true, &board).unwrap();
+        assert!(mirrored.iter().all(|p| p.piece == 'Z' && p.cells.iter().all(|&(x, _)| x >= 5)));
+    }
+
+    #[test]
+    fn pieces_still_to_place_drop_with_cleared_rows() {
+        let branch = Branch { when: String::new(), rows: vec!["____OO____".to_string(), "____OO____".to_string(), "IIII______".to_string()] };
+        let board = Board::new(10, 20);
+        let mut template = template(&branch, false, &board);
+        template.placements[0].placed = true;
+        template.cleared(&[19]);
+        let pending: Vec<&(i32, i32)> = template.pending().collect();
+        assert_eq!(pending, vec![&(4, 18), &(4, 19), &(5, 18), &(5, 19)]);
+    }
+}
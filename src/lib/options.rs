@@ -0,0 +1,230 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+
+use crate::lib::{Binding, HolePattern, MAX_BOT_DELAY, MAX_PREVIEW_COUNT, MenuInput, Settings, StatField, Visibility};
+use crate::lib::menu::draw_item;
+use crate::lib::text::draw_text;
+
+const VISIBLE_ITEMS: usize = 14;
+
+#[derive(Clone, Copy)]
+enum OptionItem {
+    Das,
+    Arr,
+    Sdf,
+    MasterVolume,
+    EffectsVolume,
+    Ghost,
+    PreviewCount,
+    Visibility,
+    FadeSeconds,
+    GarbageHoles,
+    GarbageMessiness,
+    GarbagePattern,
+    GarbageDelay,
+    Bot,
+    BotDelay,
+    Stat(StatField),
+    Binding(Binding),
+}
+
+pub enum OptionsOutcome {
+    Unchanged,
+    Changed,
+    Closed,
+}
+
+pub struct OptionsMenu {
+    items: Vec<OptionItem>,
+    selected: usize,
+    rebinding: bool,
+    refused: Option<String>,
+}
+
+impl Default for OptionsMenu {
+    fn default() -> Self {
+        OptionsMenu::new()
+    }
+}
+
+impl OptionsMenu {
+    pub fn new() -> OptionsMenu {
+        let mut items = vec![
+            OptionItem::Das,
+            OptionItem::Arr,
+            OptionItem::Sdf,
+            OptionItem::MasterVolume,
+            OptionItem::EffectsVolume,
+            OptionItem::Ghost,
+            OptionItem::PreviewCount,
+            OptionItem::Visibility,
+            OptionItem::FadeSeconds,
+            OptionItem::GarbageHoles,
+            OptionItem::GarbageMessiness,
+            OptionItem::GarbagePattern,
+            OptionItem::GarbageDelay,
+            OptionItem::Bot,
+            OptionItem::BotDelay,
+        ];
+        items.extend(StatField::ALL.iter().map(|&f| OptionItem::Stat(f)));
+        items.extend(Binding::ALL.iter().map(|&b| OptionItem::Binding(b)));
+
+        OptionsMenu {
+            items,
+            selected: 0,
+            rebinding: false,
+            refused: None,
+        }
+    }
+
+    pub fn handle(&mut self, event: &Event, settings: &mut Settings) -> OptionsOutcome {
+        if self.rebinding {
+            return match event {
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    self.rebinding = false;
+                    match self.items[self.selected] {
+                        OptionItem::Binding(binding) if *keycode != Keycode::Escape => {
+                            match settings.keys.rebind(binding, *keycode, &settings.versus.keys) {
+                                Ok(()) => OptionsOutcome::Changed,
+                                Err(e) => {
+                                    self.refused = Some(e);
+                                    OptionsOutcome::Unchanged
+                                }
+                            }
+                        }
+                        _ => OptionsOutcome::Unchanged,
+                    }
+                }
+                _ => OptionsOutcome::Unchanged,
+            };
+        }
+
+        if let Event::KeyDown { .. } | Event::ControllerButtonDown { .. } = event {
+            self.refused = None;
+        }
+        match MenuInput::from_event(event) {
+            Some(MenuInput::Back) => OptionsOutcome::Closed,
+            Some(MenuInput::Up) => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+                OptionsOutcome::Unchanged
+            }
+            Some(MenuInput::Down) => {
+                self.selected = (self.selected + 1) % self.items.len();
+                OptionsOutcome::Unchanged
+            }
+            Some(MenuInput::Left) => self.adjust(settings, -1),
+            Some(MenuInput::Right) => self.adjust(settings, 1),
+            Some(MenuInput::Select) => {
+                match self.items[self.selected] {
+                    OptionItem::Binding(_) => {
+                        self.rebinding = true;
+                        OptionsOutcome::Unchanged
+                    }
+                    _ => self.adjust(settings, 1),
+                }
+            }
+            None => OptionsOutcome::Unchanged,
+        }
+    }
+
+    fn adjust(&mut self, settings: &mut Settings, delta: i32) -> OptionsOutcome {
+        match self.items[self.selected] {
+            OptionItem::Das => settings.handling.das = step(settings.handling.das, delta, 0, 60),
+            OptionItem::Arr => settings.handling.arr = step(settings.handling.arr, delta, 0, 60),
+            OptionItem::Sdf => settings.handling.sdf = step(settings.handling.sdf, delta, 1, 100),
+            OptionItem::MasterVolume => {
+                settings.audio.master_volume = step(settings.audio.master_volume as u32, delta * 5, 0, 100) as u8
+            }
+            OptionItem::EffectsVolume => {
+                settings.audio.effects_volume = step(settings.audio.effects_volume as u32, delta * 5, 0, 100) as u8
+            }
+            OptionItem::Ghost => settings.gameplay.ghost = !settings.gameplay.ghost,
+            OptionItem::PreviewCount => {
+                settings.gameplay.preview_count = step(settings.gameplay.preview_count as u32, delta, 0, MAX_PREVIEW_COUNT as u32) as usize
+            }
+            OptionItem::Visibility => {
+                let order = [Visibility::Visible, Visibility::Fading, Visibility::Invisible];
+                let current = order.iter().position(|&v| v == settings.gameplay.visibility).unwrap_or(0);
+                settings.gameplay.visibility = order[(current as i32 + delta).rem_euclid(order.len() as i32) as usize];
+            }
+            OptionItem::FadeSeconds => settings.gameplay.fade_seconds = step(settings.gameplay.fade_seconds, delta, 1, 30),
+            OptionItem::GarbageHoles => settings.garbage.holes = step(settings.garbage.holes as u32, delta, 1, 9) as usize,
+            OptionItem::GarbageMessiness => settings.garbage.messiness = step(settings.garbage.messiness, delta * 10, 0, 100),
+            OptionItem::GarbagePattern => {
+                let order = [HolePattern::Messy, HolePattern::Clean, HolePattern::Cheese];
+                let current = order.iter().position(|&p| p == settings.garbage.pattern).unwrap_or(0);
+                settings.garbage.pattern = order[(current as i32 + delta).rem_euclid(order.len() as i32) as usize];
+            }
+            OptionItem::GarbageDelay => settings.garbage.delay = step(settings.garbage.delay, delta * 5, 0, 300),
+            OptionItem::Bot => settings.bot.enabled = !settings.bot.enabled,
+            OptionItem::BotDelay => settings.bot.delay = step(settings.bot.delay, delta, 0, MAX_BOT_DELAY),
+            OptionItem::Stat(field) => {
+                let shown = &mut settings.gameplay.stats;
+                if shown.contains(&field) {
+                    shown.retain(|&f| f != field);
+                } else {
+                    shown.push(field);
+                }
+            }
+            OptionItem::Binding(_) => return OptionsOutcome::Unchanged,
+        }
+        OptionsOutcome::Changed
+    }
+
+    pub fn draw<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, settings: &Settings) -> Result<(), String> {
+        canvas.set_draw_color(settings.theme.background.0);
+        canvas.fill_rect(Rect::new(0, 0, 800, 800))?;
+
+        draw_text(canvas, font, "Options", settings.theme.text.0, Rect::new(40, 20, 168, 50))?;
+        let first = self.selected.saturating_sub(VISIBLE_ITEMS - 1);
+        for (i, item) in self.items.iter().enumerate().skip(first).take(VISIBLE_ITEMS) {
+            let value = match item {
+                OptionItem::Das => format!("DAS: {} frames", settings.handling.das),
+                OptionItem::Arr => format!("ARR: {} frames", settings.handling.arr),
+                OptionItem::Sdf => format!("SDF: {}x", settings.handling.sdf),
+                OptionItem::MasterVolume => format!("Master volume: {}%", settings.audio.master_volume),
+                OptionItem::EffectsVolume => format!("Effects volume: {}%", settings.audio.effects_volume),
+                OptionItem::Ghost => format!("Ghost piece: {}", if settings.gameplay.ghost { "on" } else { "off" }),
+                OptionItem::PreviewCount => format!("Previews: {}", settings.gameplay.preview_count),
+                OptionItem::Visibility => format!("Locked blocks: {}", match settings.gameplay.visibility {
+                    Visibility::Visible => "visible",
+                    Visibility::Fading => "fading",
+                    Visibility::Invisible => "invisible",
+                }),
+                OptionItem::FadeSeconds => format!("Fade after: {} s", settings.gameplay.fade_seconds),
+                OptionItem::GarbageHoles => format!("Garbage holes: {}", settings.garbage.holes),
+                OptionItem::GarbageMessiness => format!("Garbage messiness: {}%", settings.garbage.messiness),
+                OptionItem::GarbagePattern => format!("Garbage holes move: {}", match settings.garbage.pattern {
+                    HolePattern::Messy => "randomly",
+                    HolePattern::Clean => "per attack",
+                    HolePattern::Cheese => "every row",
+                }),
+                OptionItem::GarbageDelay => format!("Garbage delay: {} frames", settings.garbage.delay),
+                OptionItem::Bot => format!("Bot plays player 1: {}", if settings.bot.enabled { "on" } else { "off" }),
+                OptionItem::BotDelay => format!("Bot input delay: {} frames", settings.bot.delay),
+                OptionItem::Stat(field) => {
+                    format!("Show {}: {}", field.name(), if settings.gameplay.stats.contains(field) { "on" } else { "off" })
+                }
+                OptionItem::Binding(binding) if self.rebinding && i == self.selected => {
+                    format!("{}: press a key", binding.name())
+                }
+                OptionItem::Binding(binding) if i == self.selected && self.refused.is_some() => {
+                    format!("{}: {}", binding.name(), self.refused.as_deref().unwrap_or(""))
+                }
+                OptionItem::Binding(binding) => {
+                    let keys: Vec<String> = settings.keys.keys(*binding).iter().map(|&k| k.into()).collect();
+                    format!("{}: {}", binding.name(), keys.join(", "))
+                }
+            };
+            draw_item(canvas, font, &settings.theme, &value, 40, 90 + 50 * (i - first) as i32, i == self.selected)?;
+        }
+        Ok(())
+    }
+}
+
+fn step(value: u32, delta: i32, min: u32, max: u32) -> u32 {
+    (value as i32 + delta).max(min as i32).min(max as i32) as u32
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use rand::seq::SliceRandom;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use crate::lib::PlayerPiece;
+use crate::lib::settings::ThemeColor;
+
+lazy_static! {
+    static ref STANDARD: PieceSet = PieceSet::parse(include_str!("../../pieces/tetromino.toml"))
+        .expect("the built in piece set should parse");
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PieceSet {
+    name: String,
+    pieces: Vec<PlayerPiece>,
+}
+
+#[derive(Deserialize)]
+struct PieceSetFile {
+    name: String,
+    pieces: Vec<PieceData>,
+}
+
+#[derive(Deserialize)]
+struct PieceData {
+    name: char,
+    box_size: usize,
+    spawn: (i32, i32),
+    cells: Vec<(i32, i32)>,
+    rotations: Option<Vec<Vec<(i32, i32)>>>,
+    color: Option<ThemeColor>,
+}
+
+impl PieceSet {
+    pub fn standard() -> PieceSet {
+        STANDARD.clone()
+    }
+
+    pub fn load(path: &Path) -> Result<PieceSet, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        PieceSet::parse(&contents)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(contents: &str) -> Result<PieceSet, String> {
+        let file: PieceSetFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+        if file.pieces.is_empty() {
+            return Err("a piece set needs at least one piece".to_string());
+        }
+        let mut names = HashSet::new();
+        let pieces = file.pieces.into_iter()
+            .map(|data| {
+                if !names.insert(data.name) {
+                    return Err(format!("piece {} is defined twice", data.name));
+                }
+                let box_size = data.box_size as i32;
+                let mut states = vec![data.cells];
+                match data.rotations {
+                    Some(rotations) => states.extend(rotations),
+                    // turn the cells clockwise inside their box
+                    None => for _ in 0..3 {
+                        let last = &states[states.len() - 1];
+                        let next = last.iter().map(|&(x, y)| (box_size - 1 - y, x)).collect();
+                        states.push(next);
+                    },
+                }
+                PlayerPiece::new(data.name, data.box_size, data.spawn, states, data.color.map(|c| c.0))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(PieceSet { name: file.name, pieces })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn find(&self, name: char) -> Option<PlayerPiece> {
+        self.pieces.iter().find(|p| p.name() == name).cloned()
+    }
+
+    pub fn shuffled(&self, rng: &mut Pcg32) -> Vec<PlayerPiece> {
+        let mut pieces = self.pieces.clone();
+        pieces.shuffle(rng);
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_sets_parse() {
+        for path in ["pieces/tetromino.toml", "pieces/tromino.toml", "pieces/pentomino.toml"].iter() {
+            let set = PieceSet::load(Path::new(path)).unwrap();
+            assert!(!set.pieces.is_empty(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn rotations_that_are_not_clockwise_turns_are_refused() {
+        let error = PieceSet::parse(r#"
+            name = "bad"
+            [[pieces]]
+            name = "L"
+            box_size = 3
+            spawn = [3, 0]
+            cells = [[0, 1], [1, 1], [2, 1], [2, 0]]
+            rotations = [[[1, 0], [1, 1], [1, 2], [2, 2]], [[0, 1], [1, 1], [2, 1], [2, 0]], [[0, 0], [1, 0], [1, 1], [1, 2]]]
+        "#).err().unwrap();
+        assert_eq!(error, "piece L rotation 2 isn't rotation 1 turned clockwise");
+    }
+
+    #[test]
+    fn rotation_counts_have_to_divide_a_full_turn() {
+        let error = PieceSet::parse(r#"
+            name = "bad"
+            [[pieces]]
+            name = "I"
+            box_size = 3
+            spawn = [3, 0]
+            cells = [[0, 1], [1, 1], [2, 1]]
+            rotations = [[[1, 0], [1, 1], [1, 2]], [[0, 1], [1, 1], [2, 1]]]
+        "#).err().unwrap();
+        assert_eq!(error, "piece I has 3 rotation states, which don't make a full turn");
+    }
+
+    #[test]
+    fn symmetric_pieces_can_have_one_state() {
+        let set = PieceSet::parse(r#"
+            name = "plus"
+            [[pieces]]
+            name = "X"
+            box_size = 3
+            spawn = [3, 0]
+            cells = [[1, 0], [0, 1], [1, 1], [2, 1], [1, 2]]
+            rotations = []
+        "#).unwrap();
+        assert!(set.find('X').is_some());
+    }
+
+    #[test]
+    fn only_the_t_tetromino_counts_as_a_t() {
+        assert!(PieceSet::standard().find('T').unwrap().is_t());
+        assert!(!PieceSet::standard().find('L').unwrap().is_t());
+        let pentomino_t = PieceSet::load(Path::new("pieces/pentomino.toml")).unwrap().find('T').unwrap();
+        assert!(!pentomino_t.is_t());
+
+        let renamed = PieceSet::parse(r#"
+            name = "renamed"
+            [[pieces]]
+            name = "A"
+            box_size = 3
+            spawn = [3, 0]
+            cells = [[0, 1], [1, 1], [2, 1], [1, 0]]
+        "#).unwrap();
+        assert!(renamed.find('A').unwrap().is_t());
+    }
+}
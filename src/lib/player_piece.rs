@@ -1,133 +1,168 @@
 use std::ops::Add;
 
-use lazy_static::lazy_static;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use std::convert::TryFrom;
+
+use rand::{Rng, SeedableRng, thread_rng};
+use rand_pcg::Pcg32;
 use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
 use sdl2::render::WindowCanvas;
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Board, PieceSet, Theme};
+use crate::lib::settings::ThemeColor;
 
+// states holds the piece's cells for each rotation, in blocks inside a box_size square; a piece
+// with a scale of 2 draws each block as 2x2 cells and moves two cells at a time
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "SavedPiece", into = "SavedPiece")]
 pub struct PlayerPiece {
     anchor: Point,
+    spawn: Point,
     box_size: usize,
-    tiles: [Point; 4],
-    stationary: bool,
+    states: Vec<Vec<Point>>,
+    rotation: usize,
+    name: char,
+    color: Option<Color>,
+    scale: i32,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PieceBag {
+    set: PieceSet,
+    endless: bool,
     remaining: Vec<PlayerPiece>,
     queued: Vec<PlayerPiece>,
+    column: i32,
+    seed: u64,
+    rng: Pcg32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPiece {
+    anchor: (i32, i32),
+    spawn: (i32, i32),
+    box_size: usize,
+    states: Vec<Vec<(i32, i32)>>,
+    rotation: usize,
+    name: char,
+    color: Option<ThemeColor>,
+    scale: i32,
 }
 
-lazy_static! {
-    static ref OPTIONS: [PlayerPiece; 7] = [
-        PlayerPiece{ // square
-            anchor: Point::new(3, 0),
-            box_size: 4,
-            tiles: [
-                Point::new(1, 1),
-                Point::new(2, 1),
-                Point::new(1, 2),
-                Point::new(2, 2),
-            ],
-            stationary: false
-        },
-        PlayerPiece{ // T shape
-            anchor: Point::new(3, 0),
-            box_size: 3,
-            tiles: [
-                Point::new(0, 1),
-                Point::new(1, 1),
-                Point::new(2, 1),
-                Point::new(1, 0),
-            ],
-            stationary: false
-        },
-        PlayerPiece{ // long
-            anchor: Point::new(3, 0),
-            box_size: 4,
-            tiles: [
-                Point::new(0, 1),
-                Point::new(1, 1),
-                Point::new(2, 1),
-                Point::new(3, 1),
-            ],
-            stationary: false
-        },
-        PlayerPiece{ // S 1
-            anchor: Point::new(3, 0),
-            box_size: 3,
-            tiles: [
-                Point::new(0, 1),
-                Point::new(1, 1),
-                Point::new(1, 2),
-                Point::new(2, 2),
-            ],
-            stationary: false
-        },
-        PlayerPiece{ // S 2
-            anchor: Point::new(3, 0),
-            box_size: 3,
-            tiles: [
-                Point::new(0, 1),
-                Point::new(1, 1),
-                Point::new(1, 0),
-                Point::new(2, 0),
-            ],
-            stationary: false
-        },
-        PlayerPiece{ // L 1
-            anchor: Point::new(3, 0),
-            box_size: 3,
-            tiles: [
-                Point::new(0, 1),
-                Point::new(1, 1),
-                Point::new(2, 1),
-                Point::new(2, 2),
-            ],
-            stationary: false
-        },
-        PlayerPiece{ // L 2
-            anchor: Point::new(3, 0),
-            box_size: 3,
-            tiles: [
-                Point::new(0, 1),
-                Point::new(1, 1),
-                Point::new(2, 1),
-                Point::new(2, 0),
-            ],
-            stationary: false
+impl From<PlayerPiece> for SavedPiece {
+    fn from(piece: PlayerPiece) -> Self {
+        SavedPiece {
+            anchor: (piece.anchor.x(), piece.anchor.y()),
+            spawn: (piece.spawn.x(), piece.spawn.y()),
+            box_size: piece.box_size,
+            states: piece.states.iter()
+                .map(|state| state.iter().map(|t| (t.x(), t.y())).collect())
+                .collect(),
+            rotation: piece.rotation,
+            name: piece.name,
+            color: piece.color.map(ThemeColor),
+            scale: piece.scale,
         }
-    ];
+    }
 }
 
-impl Clone for PlayerPiece {
-    fn clone(&self) -> Self {
-        Self {
-            anchor: self.anchor,
-            box_size: self.box_size,
-            tiles: self.tiles,
-            stationary: self.stationary,
+impl TryFrom<SavedPiece> for PlayerPiece {
+    type Error = String;
+
+    fn try_from(saved: SavedPiece) -> Result<Self, Self::Error> {
+        if saved.rotation >= saved.states.len() {
+            return Err(format!("piece {} is in rotation {} of {}", saved.name, saved.rotation, saved.states.len()));
         }
+        if saved.scale < 1 {
+            return Err(format!("piece {} has a scale of {}", saved.name, saved.scale));
+        }
+        let mut piece = PlayerPiece::new(saved.name, saved.box_size, saved.spawn, saved.states, saved.color.map(|c| c.0))?;
+        piece.anchor = Point::new(saved.anchor.0, saved.anchor.1);
+        piece.rotation = saved.rotation;
+        piece.scale = saved.scale;
+        Ok(piece)
     }
 }
 
 impl PlayerPiece {
-    pub fn new(i: usize) -> PlayerPiece {
-        OPTIONS[i].clone()
+    pub fn new(name: char, box_size: usize, spawn: (i32, i32), states: Vec<Vec<(i32, i32)>>, color: Option<Color>) -> Result<PlayerPiece, String> {
+        if states.is_empty() || states[0].is_empty() {
+            return Err(format!("piece {} has no cells", name));
+        }
+        for state in states.iter() {
+            if state.len() != states[0].len() {
+                return Err(format!("piece {} changes its number of cells when rotated", name));
+            }
+            if let Some(&(x, y)) = state.iter().find(|&&(x, y)| x < 0 || y < 0 || x >= box_size as i32 || y >= box_size as i32) {
+                return Err(format!("piece {} has cell ({}, {}) outside its {}x{} box", name, x, y, box_size, box_size));
+            }
+        }
+        // rotating four times has to come back round, each state being the one before turned clockwise
+        if 4 % states.len() != 0 {
+            return Err(format!("piece {} has {} rotation states, which don't make a full turn", name, states.len()));
+        }
+        for (i, state) in states.iter().enumerate() {
+            let next = (i + 1) % states.len();
+            if shape(&turned(state)) != shape(&states[next]) {
+                return Err(format!("piece {} rotation {} isn't rotation {} turned clockwise", name, next, i));
+            }
+        }
+        Ok(PlayerPiece {
+            anchor: Point::new(spawn.0, spawn.1),
+            spawn: Point::new(spawn.0, spawn.1),
+            box_size,
+            states: states.iter()
+                .map(|state| state.iter().map(|&(x, y)| Point::new(x, y)).collect())
+                .collect(),
+            rotation: 0,
+            name,
+            color,
+            scale: 1,
+        })
+    }
+
+    // the same piece drawn at the given scale, with its anchor snapped to the coarser grid
+    pub fn scaled(&self, scale: i32) -> PlayerPiece {
+        let mut piece = self.clone();
+        piece.scale = scale;
+        piece.anchor = Point::new(self.anchor.x() / scale * scale, self.anchor.y() / scale * scale);
+        piece
+    }
+
+    pub fn scale(&self) -> i32 {
+        self.scale
+    }
+
+    pub fn spawn(&self) -> Point {
+        Point::new(self.spawn.x() / self.scale * self.scale, self.spawn.y() / self.scale * self.scale)
+    }
+
+    // the same piece back where it started
+    pub fn respawned(&self) -> PlayerPiece {
+        let mut piece = self.clone();
+        piece.anchor = self.spawn();
+        piece.rotation = 0;
+        piece
+    }
+
+    pub fn box_size(&self) -> usize {
+        self.box_size
+    }
+
+    pub fn color(&self) -> Option<Color> {
+        self.color
     }
 
-    pub fn shuffled() -> Vec<PlayerPiece> {
-        let mut tiles: Vec<_> = (0..7).map(|i| PlayerPiece::new(i)).collect();
-        tiles.shuffle(&mut thread_rng());
-        tiles
+    pub fn rotation(&self) -> usize {
+        self.rotation
     }
 
-    pub fn base(&self) -> [Point; 4] {
-        self.tiles
+    pub fn base(&self) -> &[Point] {
+        &self.states[self.rotation]
     }
 
-    pub fn rotate(&self, board: &[[bool; 20]; 10]) -> Option<PlayerPiece> {
+    pub fn rotate(&self, board: &Board) -> Option<PlayerPiece> {
         let new = self.try_rotate();
         if new.legal(board) {
             return Some(new);
@@ -137,7 +172,7 @@ impl PlayerPiece {
                     return Some(piece_right);
                 }
             }
-        } else if self.anchor.x + self.box_size as i32 >= board.len() as i32 {
+        } else if self.anchor.x + self.box_size as i32 * self.scale >= board.width() as i32 {
             if let Some(piece_left) = self.go_left(board).map(|p| p.try_rotate()) {
                 if piece_left.legal(board) {
                     return Some(piece_left);
@@ -148,16 +183,12 @@ impl PlayerPiece {
     }
 
     fn try_rotate(&self) -> PlayerPiece {
-        let mut pieces: [Point; 4] = [Point::new(0, 0); 4];
-        for (i, p) in self.tiles.iter().enumerate() {
-            pieces[i] = Point::new(self.box_size as i32 - 1 - p.y(), p.x());
-        }
         let mut new_piece = self.clone();
-        new_piece.tiles = pieces;
+        new_piece.rotation = (self.rotation + 1) % self.states.len();
         new_piece
     }
 
-    pub fn lowest_possible_position(&self, board: &[[bool; 20]; 10]) -> PlayerPiece {
+    pub fn lowest_possible_position(&self, board: &Board) -> PlayerPiece {
         let mut res = self.clone();
         while let Some(b) = res.go_down(board) {
             res = b;
@@ -166,23 +197,33 @@ impl PlayerPiece {
     }
 
     pub fn get_tiles(&self) -> Vec<Point> {
-        self.tiles.iter()
+        let scale = self.scale;
+        self.base().iter()
+            .flat_map(|t| (0..scale * scale).map(move |i| Point::new(t.x() * scale + i % scale, t.y() * scale + i / scale)))
             .map(|t| t.add(self.anchor))
             .collect()
     }
 
-    pub fn is_stationary(&self) -> bool {
-        self.stationary
+    pub fn name(&self) -> char {
+        self.name
     }
 
-    pub fn set_stationary(&self, s: bool) -> PlayerPiece {
-        let mut new_piece = self.clone();
-        new_piece.stationary = s;
-        new_piece
+    // the t tetromino in a 3x3 box, whatever it's called, since t-spins depend on the shape
+    pub fn is_t(&self) -> bool {
+        let cells: Vec<(i32, i32)> = self.states[0].iter().map(|p| (p.x(), p.y())).collect();
+        let mut t = vec![(0, 1), (1, 1), (2, 1), (1, 0)];
+        self.box_size == 3 && (0..4).any(|_| {
+            t = turned(&t);
+            shape(&t) == shape(&cells)
+        })
+    }
+
+    pub fn anchor(&self) -> Point {
+        self.anchor
     }
 
-    pub fn go_left(&self, board: &[[bool; 20]; 10]) -> Option<PlayerPiece> {
-        let left = self.move_piece(-1, 0);
+    pub fn go_left(&self, board: &Board) -> Option<PlayerPiece> {
+        let left = self.move_piece(-self.scale, 0);
         if left.legal(board) {
             Some(left)
         } else {
@@ -190,8 +231,8 @@ impl PlayerPiece {
         }
     }
 
-    pub fn go_right(&self, board: &[[bool; 20]; 10]) -> Option<PlayerPiece> {
-        let right = self.move_piece(1, 0);
+    pub fn go_right(&self, board: &Board) -> Option<PlayerPiece> {
+        let right = self.move_piece(self.scale, 0);
         if right.legal(board) {
             Some(right)
         } else {
@@ -199,8 +240,8 @@ impl PlayerPiece {
         }
     }
 
-    pub fn go_down(&self, board: &[[bool; 20]; 10]) -> Option<PlayerPiece> {
-        let down = self.move_piece(0, 1);
+    pub fn go_down(&self, board: &Board) -> Option<PlayerPiece> {
+        let down = self.move_piece(0, self.scale);
         if down.legal(board) {
             Some(down)
         } else {
@@ -208,63 +249,166 @@ impl PlayerPiece {
         }
     }
 
+    // no checks, for getting out of the way of blocks that moved into the piece
+    pub fn lifted(&self) -> PlayerPiece {
+        self.move_piece(0, -self.scale)
+    }
+
+    // moves the piece along with its spawn point, so it comes back to the same place when held
+    pub fn shifted(&self, columns: i32) -> PlayerPiece {
+        let mut piece = self.move_piece(columns, 0);
+        piece.spawn = piece.spawn.offset(columns, 0);
+        piece
+    }
+
     fn move_piece(&self, x: i32, y: i32) -> PlayerPiece {
         let mut new_piece = self.clone();
         new_piece.anchor = new_piece.anchor.offset(x, y);
         new_piece
     }
 
-    fn legal(&self, board: &[[bool; 20]; 10]) -> bool {
-        self.get_tiles().iter().all(|t| t.x() >= 0 && t.x() < board.len() as i32 && t.y() >= 0 && t.y() < board[0].len() as i32
-            && !board[t.x() as usize][t.y() as usize])
+    pub fn legal(&self, board: &Board) -> bool {
+        self.get_tiles().iter().all(|t| board.is_free(t.x(), t.y()))
     }
 
-    pub fn draw(&self, canvas: &mut WindowCanvas, board: &[[bool; 20]; 10]) -> Result<(), String> {
-        canvas.set_draw_color(Color::RGB(128, 50, 200));
+    pub fn draw(&self, canvas: &mut WindowCanvas, board: &Board, theme: &Theme, ghost: bool) -> Result<(), String> {
+        canvas.set_draw_color(self.color.unwrap_or(theme.piece.0));
         for p in &self.get_tiles() {
             canvas.fill_rect(Rect::new(p.x() * 40, p.y() * 40, 40, 40))?
         }
 
-        canvas.set_draw_color(Color::RGB(0, 255, 255));
-        for p in &self.lowest_possible_position(board).get_tiles() {
-            canvas.draw_rect(Rect::new(p.x() * 40 + 1, p.y() * 40 + 1, 38, 38))?;
+        if ghost {
+            canvas.set_draw_color(theme.ghost.0);
+            for p in &self.lowest_possible_position(board).get_tiles() {
+                canvas.draw_rect(Rect::new(p.x() * 40 + 1, p.y() * 40 + 1, 38, 38))?;
+            }
         }
 
         Ok(())
     }
 }
 
+impl Iterator for PieceBag {
+    type Item = PlayerPiece;
+
+    fn next(&mut self) -> Option<PlayerPiece> {
+        if self.remaining.is_empty() && self.endless {
+            std::mem::swap(&mut self.remaining, &mut self.queued);
+            let column = self.column;
+            self.queued = self.set.shuffled(&mut self.rng).iter().map(|p| p.shifted(column)).collect();
+        }
+        self.remaining.pop()
+    }
+}
+
 impl PieceBag {
-    pub fn new() -> Self {
+    pub fn new(set: &PieceSet) -> Self {
+        PieceBag::seeded(set, thread_rng().gen())
+    }
+
+    fn seeded(set: &PieceSet, seed: u64) -> Self {
+        let mut rng = Pcg32::seed_from_u64(seed);
         PieceBag {
-            remaining: PlayerPiece::shuffled(),
-            queued: PlayerPiece::shuffled(),
+            set: set.clone(),
+            endless: true,
+            remaining: set.shuffled(&mut rng),
+            queued: set.shuffled(&mut rng),
+            column: 0,
+            seed,
+            rng,
         }
     }
 
-    pub fn next(&mut self) -> PlayerPiece {
-        match self.remaining.pop() {
-            Some(piece) => piece,
-            None => {
-                std::mem::swap(&mut self.remaining, &mut self.queued);
-                self.queued = PlayerPiece::shuffled();
-                self.remaining.pop().unwrap()
-            }
+    pub fn with_queue(set: &PieceSet, mut queue: Vec<PlayerPiece>) -> Self {
+        let seed = thread_rng().gen();
+        let mut rng = Pcg32::seed_from_u64(seed);
+        queue.reverse();
+        PieceBag {
+            set: set.clone(),
+            endless: true,
+            remaining: queue,
+            queued: set.shuffled(&mut rng),
+            column: 0,
+            seed,
+            rng,
         }
     }
 
-    pub fn peek(&self) -> &PlayerPiece {
-        if self.remaining.len() != 0 {
-            &self.remaining[self.remaining.len() - 1]
+    // a bag that only ever deals the given queue
+    pub fn fixed(set: &PieceSet, queue: Vec<PlayerPiece>) -> Self {
+        let mut bag = PieceBag::with_queue(set, queue);
+        bag.endless = false;
+        bag.queued.clear();
+        bag
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // bags reseeded alike deal the same pieces, so versus players get an even start
+    pub fn reseed(&mut self, seed: u64) {
+        let column = self.column;
+        *self = PieceBag::seeded(&self.set, seed);
+        self.set_column(column);
+    }
+
+    // pieces dealt from here on spawn this many columns over from their usual spot
+    pub fn set_column(&mut self, column: i32) {
+        let shift = column - self.column;
+        for piece in self.remaining.iter_mut().chain(self.queued.iter_mut()) {
+            *piece = piece.shifted(shift);
+        }
+        self.column = column;
+    }
+
+    pub fn left(&self) -> Option<usize> {
+        if self.endless {
+            None
         } else {
-            &self.queued[self.queued.len() - 1]
+            Some(self.remaining.len())
         }
     }
 
-    pub fn swap(&mut self, mut piece: PlayerPiece) -> PlayerPiece {
-        let mut out = self.next();
-        std::mem::swap(&mut out.anchor, &mut piece.anchor);
+    pub fn set_name(&self) -> &str {
+        self.set.name()
+    }
+
+    pub fn peek_many(&self, count: usize) -> Vec<&PlayerPiece> {
+        self.remaining.iter().rev()
+            .chain(self.queued.iter().rev())
+            .take(count)
+            .collect()
+    }
+
+    // puts a piece back on top of the bag, to be dealt again next
+    pub fn deal_next(&mut self, piece: PlayerPiece) {
         self.remaining.push(piece);
-        out
     }
+
+    // the held piece goes back to its own spawn point, keeping its orientation; with nothing left to swap in it stays put
+    pub fn swap(&mut self, mut piece: PlayerPiece) -> PlayerPiece {
+        match self.next() {
+            Some(out) => {
+                piece.anchor = piece.spawn;
+                self.remaining.push(piece);
+                out
+            }
+            None => piece,
+        }
+    }
+}
+
+// the cells moved up against the top and left, in order, so shapes can be compared wherever they are
+fn shape(cells: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let left = cells.iter().map(|c| c.0).min().unwrap_or(0);
+    let top = cells.iter().map(|c| c.1).min().unwrap_or(0);
+    let mut cells: Vec<(i32, i32)> = cells.iter().map(|&(x, y)| (x - left, y - top)).collect();
+    cells.sort();
+    cells
+}
+
+// a quarter turn clockwise, with y growing downwards
+fn turned(cells: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    cells.iter().map(|&(x, y)| (-y, x)).collect()
 }
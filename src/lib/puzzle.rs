@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{GameState, Goal, PieceSet, Speed};
+
+#[derive(Deserialize)]
+pub struct PuzzlePack {
+    pub name: String,
+    pub puzzles: Vec<Puzzle>,
+}
+
+// the board comes from a fumen, from rows drawn with '_' for empty cells and 'X' for garbage
+// (the last row sitting on the floor), or from both with the rows going on top of the fumen
+#[derive(Deserialize)]
+pub struct Puzzle {
+    pub name: String,
+    #[serde(default)]
+    fumen: Option<String>,
+    #[serde(default)]
+    rows: Vec<String>,
+    queue: String,
+    #[serde(default = "hold_allowed")]
+    hold: bool,
+    goal: Goal,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PuzzleProgress {
+    solved: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl PuzzlePack {
+    pub fn load_all(dir: &Path) -> Vec<PuzzlePack> {
+        let mut paths: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return vec![],
+        };
+        paths.sort();
+
+        paths.iter()
+            .filter(|path| path.extension().is_some_and(|e| e == "toml"))
+            .filter_map(|path| match PuzzlePack::load(path) {
+                Ok(pack) => Some(pack),
+                Err(e) => {
+                    eprintln!("skipping puzzle pack {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn load(path: &Path) -> Result<PuzzlePack, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let pack: PuzzlePack = toml::from_str(&contents).map_err(|e| e.to_string())?;
+        for puzzle in pack.puzzles.iter() {
+            puzzle.game_state().map_err(|e| format!("{}: {}", puzzle.name, e))?;
+        }
+        Ok(pack)
+    }
+}
+
+impl Puzzle {
+    pub fn game_state(&self) -> Result<GameState, String> {
+        let pieces = PieceSet::standard();
+        let mut game_state = match &self.fumen {
+            Some(fumen) => GameState::from_fumen(fumen)?,
+            None => GameState::new(Goal::None, Speed::Guideline, 1, &pieces),
+        };
+        game_state.set_rows(&self.rows)?;
+
+        let queue = self.queue.chars()
+            .map(|name| pieces.find(name).ok_or_else(|| format!("there is no {} piece", name)))
+            .collect::<Result<Vec<_>, String>>()?;
+        if queue.is_empty() {
+            return Err("the queue is empty".to_string());
+        }
+        match self.goal {
+            Goal::Lines(_) | Goal::Garbage | Goal::PerfectClear | Goal::TSpin(_) => (),
+            _ => return Err("puzzles can only clear lines, garbage, the whole board or a t-spin".to_string()),
+        }
+        game_state.set_puzzle(self.goal, &pieces, queue, self.hold);
+        Ok(game_state)
+    }
+
+    pub fn description(&self) -> String {
+        match self.goal {
+            Goal::Lines(1) => "Clear a line".to_string(),
+            Goal::Lines(lines) => format!("Clear {} lines", lines),
+            Goal::Garbage => "Clear all the garbage".to_string(),
+            Goal::PerfectClear => format!("Perfect clear in {} pieces", self.queue.chars().count()),
+            Goal::TSpin(1) => "T-spin single".to_string(),
+            Goal::TSpin(2) => "T-spin double".to_string(),
+            Goal::TSpin(_) => "T-spin triple".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl PuzzleProgress {
+    pub fn load(path: &Path) -> Result<PuzzleProgress, String> {
+        if !path.exists() {
+            return Ok(PuzzleProgress::default());
+        }
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    pub fn is_solved(&self, pack: &str, puzzle: &str) -> bool {
+        self.solved.get(pack).is_some_and(|solved| solved.contains(puzzle))
+    }
+
+    pub fn solved(&self, pack: &PuzzlePack) -> usize {
+        pack.puzzles.iter().filter(|p| self.is_solved(&pack.name, &p.name)).count()
+    }
+
+    pub fn mark(&mut self, pack: &str, puzzle: &str) {
+        self.solved.entry(pack.to_string()).or_default().insert(puzzle.to_string());
+    }
+}
+
+fn hold_allowed() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use super::*;
+    use crate::lib::Action;
+
+    fn puzzle(text: &str) -> Result<GameState, String> {
+        toml::from_str::<Puzzle>(text).map_err(|e| e.to_string())?.game_state()
+    }
+
+    // spawns the next piece, presses the keys and hard drops it
+    fn place(game_state: &mut GameState, actions: &[Action]) {
+        let (audio, _sounds) = sync_channel(1000);
+        while game_state.position(0, 0).is_none() {
+            game_state.update(audio.clone(), 1).unwrap();
+        }
+        for &action in actions.iter().chain([Action::HardDrop].iter()) {
+            game_state.press(action);
+        }
+        game_state.update(audio, 1);
+    }
+
+    const TETRIS: &str = r#"
+        name = "Tetris"
+        queue = "I"
+        goal = { Lines = 4 }
+        rows = ["XXXXXXXXX_", "XXXXXXXXX_", "XXXXXXXXX_", "XXXXXXXXX_"]
+    "#;
+
+    #[test]
+    fn shipped_packs_load() {
+        let packs = PuzzlePack::load_all(Path::new("puzzles"));
+        assert!(!packs.is_empty());
+        assert!(packs.iter().all(|pack| !pack.puzzles.is_empty()));
+    }
+
+    #[test]
+    fn filling_the_well_passes() {
+        let mut game_state = puzzle(TETRIS).unwrap();
+        let right = [Action::Rotate, Action::MoveRight, Action::MoveRight, Action::MoveRight, Action::MoveRight];
+        place(&mut game_state, &right);
+        assert_eq!(game_state.cleared(), 4);
+        assert!(game_state.finished());
+    }
+
+    #[test]
+    fn missing_the_well_fails() {
+        let mut game_state = puzzle(TETRIS).unwrap();
+        place(&mut game_state, &[]);
+        assert!(!game_state.finished());
+        assert_eq!(game_state.pieces_left(), Some(0));
+    }
+
+    #[test]
+    fn perfect_clears_need_an_empty_board() {
+        let text = r#"
+            name = "PC"
+            queue = "O"
+            goal = "PerfectClear"
+            rows = ["XXXXXXXX__", "XXXXXXXX__"]
+        "#;
+        let mut game_state = puzzle(text).unwrap();
+        place(&mut game_state, &[Action::MoveRight, Action::MoveRight, Action::MoveRight, Action::MoveRight]);
+        assert!(game_state.finished());
+
+        let mut game_state = puzzle(&text.replace("XXXXXXXX__\", \"XXXXXXXX__", "_XXXXXXX__\", \"XXXXXXXX__")).unwrap();
+        place(&mut game_state, &[Action::MoveRight, Action::MoveRight, Action::MoveRight, Action::MoveRight]);
+        assert!(!game_state.finished());
+    }
+
+    #[test]
+    fn garbage_goals_need_every_garbage_row_gone() {
+        let text = r#"
+            name = "Dig"
+            queue = "OO"
+            goal = "Garbage"
+            rows = ["XXXXXXXX__", "XXXXXXXX__"]
+        "#;
+        let mut game_state = puzzle(text).unwrap();
+        place(&mut game_state, &[]);
+        assert!(!game_state.finished());
+        place(&mut game_state, &[Action::MoveRight, Action::MoveRight, Action::MoveRight, Action::MoveRight]);
+        assert!(game_state.finished());
+    }
+
+    #[test]
+    fn bad_puzzles_are_refused() {
+        assert_eq!(puzzle(&TETRIS.replace("queue = \"I\"", "queue = \"\"")).err().unwrap(), "the queue is empty");
+        assert_eq!(puzzle(&TETRIS.replace("queue = \"I\"", "queue = \"Q\"")).err().unwrap(), "there is no Q piece");
+        assert!(puzzle(&TETRIS.replace("goal = { Lines = 4 }", "goal = { Time = 1000 }")).is_err());
+        assert!(puzzle(&TETRIS.replace("XXXXXXXXX_\", \"XXXXXXXXX_\", \"XXXXXXXXX_\", \"XXXXXXXXX_", "XXX")).is_err());
+    }
+
+    #[test]
+    fn progress_is_kept_per_pack() {
+        let mut progress = PuzzleProgress::default();
+        progress.mark("Basics", "Tetris");
+        assert!(progress.is_solved("Basics", "Tetris"));
+        assert!(!progress.is_solved("Other", "Tetris"));
+    }
+}
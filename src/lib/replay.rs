@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::SyncSender;
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Action, GameState, Mode, Sound};
+
+const MAX_REPLAYS: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum ReplayInput {
+    Press(Action),
+    Action(Action),
+    SoftDropFactor(u64),
+    Edit { x: usize, y: usize, filled: bool },
+    // the second player's inputs in co-op
+    PartnerPress(Action),
+    PartnerAction(Action),
+    PartnerSoftDropFactor(u64),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub mode: Mode,
+    pub date: NaiveDateTime,
+    pub score: u64,
+    initial: GameState,
+    inputs: Vec<(u64, ReplayInput)>,
+    frames: u64,
+}
+
+pub struct ReplayRecorder {
+    initial: GameState,
+    inputs: Vec<(u64, ReplayInput)>,
+    frame: u64,
+    soft_drop_factors: [u64; 2],
+}
+
+pub struct ReplayPlayer {
+    replay: Replay,
+    frame: u64,
+    next_input: usize,
+    soft_drop_factors: [u64; 2],
+}
+
+impl Replay {
+    pub fn load_all(dir: &Path) -> Vec<Replay> {
+        let mut paths: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return vec![],
+        };
+        paths.sort();
+        paths.reverse();
+
+        paths.iter()
+            .take(MAX_REPLAYS)
+            .filter_map(|path| {
+                let contents = fs::read_to_string(path).ok()?;
+                match serde_json::from_str(&contents) {
+                    Ok(replay) => Some(replay),
+                    Err(e) => {
+                        eprintln!("skipping replay {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn store(&self, dir: &Path) -> Result<(), String> {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        // named down to the millisecond, with a count after it for any saved closer together than that
+        let name = self.date.format("%Y%m%d-%H%M%S-%3f").to_string();
+        let path = (0..)
+            .map(|n| dir.join(if n == 0 { format!("{}.json", name) } else { format!("{}-{}.json", name, n) }))
+            .find(|path| !path.exists())
+            .expect("some count should be free");
+        let contents = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())?;
+
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(|e| e.to_string())?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect();
+        paths.sort();
+        if paths.len() > MAX_REPLAYS {
+            for old in &paths[..paths.len() - MAX_REPLAYS] {
+                fs::remove_file(old).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReplayRecorder {
+    pub fn new(initial: &GameState) -> ReplayRecorder {
+        ReplayRecorder {
+            initial: initial.clone(),
+            inputs: vec![],
+            frame: 0,
+            soft_drop_factors: [1, 1],
+        }
+    }
+
+    pub fn record(&mut self, input: ReplayInput) {
+        let seat = match input {
+            ReplayInput::SoftDropFactor(factor) => Some((0, factor)),
+            ReplayInput::PartnerSoftDropFactor(factor) => Some((1, factor)),
+            _ => None,
+        };
+        if let Some((seat, factor)) = seat {
+            if factor == self.soft_drop_factors[seat] {
+                return;
+            }
+            self.soft_drop_factors[seat] = factor;
+        }
+        self.inputs.push((self.frame, input));
+    }
+
+    pub fn advance(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn finish(self, mode: Mode, score: u64) -> Replay {
+        Replay {
+            mode,
+            date: Local::now().naive_local(),
+            score,
+            initial: self.initial,
+            inputs: self.inputs,
+            frames: self.frame,
+        }
+    }
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> (ReplayPlayer, GameState) {
+        let game_state = replay.initial.clone();
+        let player = ReplayPlayer {
+            replay,
+            frame: 0,
+            next_input: 0,
+            soft_drop_factors: [1, 1],
+        };
+        (player, game_state)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.replay.frames
+    }
+
+    // replays the inputs recorded for the current frame, then steps the game exactly as the recorder saw it
+    pub fn tick(&mut self, game_state: &mut GameState, audio: SyncSender<Sound>) -> Option<u64> {
+        while let Some(&(frame, input)) = self.replay.inputs.get(self.next_input) {
+            if frame != self.frame {
+                break;
+            }
+            match input {
+                ReplayInput::Press(action) => game_state.press(action),
+                ReplayInput::Action(action) => game_state.apply(action),
+                ReplayInput::SoftDropFactor(factor) => self.soft_drop_factors[0] = factor,
+                ReplayInput::Edit { x, y, filled } => game_state.set_tile(x, y, filled),
+                ReplayInput::PartnerPress(action) => game_state.press_as(1, action),
+                ReplayInput::PartnerAction(action) => game_state.apply_as(1, action),
+                ReplayInput::PartnerSoftDropFactor(factor) => self.soft_drop_factors[1] = factor,
+            }
+            self.next_input += 1;
+        }
+        self.frame += 1;
+        game_state.update_seats(audio, &self.soft_drop_factors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use crate::lib::{Goal, PieceSet, Speed};
+
+    use super::*;
+
+    fn tiles(game_state: &GameState, seat: usize) -> Option<Vec<(i32, i32)>> {
+        game_state.position(seat, 0).map(|p| p.piece.get_tiles().iter().map(|t| (t.x(), t.y())).collect())
+    }
+
+    #[test]
+    fn coop_replays_play_back_both_seats() {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard());
+        game_state.set_players(2, 20);
+        let mut recorder = ReplayRecorder::new(&game_state);
+
+        for frame in 0..150 {
+            let (input, partner, factor) = match frame % 30 {
+                5 => (Some(ReplayInput::Press(Action::MoveLeft)), Some(ReplayInput::PartnerPress(Action::Rotate)), 1),
+                10 => (None, Some(ReplayInput::PartnerAction(Action::MoveRight)), 20),
+                20 => (Some(ReplayInput::Press(Action::HardDrop)), None, 1),
+                25 => (None, Some(ReplayInput::PartnerPress(Action::HardDrop)), 1),
+                _ => (None, None, if frame % 30 > 10 { 20 } else { 1 }),
+            };
+            if let Some(ReplayInput::Press(action)) = input {
+                game_state.press(action);
+            }
+            match partner {
+                Some(ReplayInput::PartnerPress(action)) => game_state.press_as(1, action),
+                Some(ReplayInput::PartnerAction(action)) => game_state.apply_as(1, action),
+                _ => (),
+            }
+            for input in input.into_iter().chain(partner) {
+                recorder.record(input);
+            }
+            recorder.record(ReplayInput::SoftDropFactor(1));
+            recorder.record(ReplayInput::PartnerSoftDropFactor(factor));
+            game_state.update_seats(audio.clone(), &[1, factor]).unwrap();
+            recorder.advance();
+        }
+
+        let (mut player, mut replayed) = ReplayPlayer::new(recorder.finish(Mode::Coop, 0));
+        while !player.finished() {
+            player.tick(&mut replayed, audio.clone()).unwrap();
+        }
+        assert!(game_state.pieces() >= 8);
+        assert_eq!(replayed.pieces(), game_state.pieces());
+        assert_eq!(replayed.stats().keys, game_state.stats().keys);
+        assert_eq!(replayed.stats().finesse_faults, game_state.stats().finesse_faults);
+        for seat in 0..2 {
+            assert_eq!(replayed.cleared_by(seat), game_state.cleared_by(seat));
+            assert_eq!(tiles(&replayed, seat), tiles(&game_state, seat));
+        }
+    }
+
+    #[test]
+    fn replays_saved_together_are_all_kept() {
+        let dir = std::env::temp_dir().join(format!("tetris-replays-{}", std::process::id()));
+        let game_state = GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard());
+        let replay = ReplayRecorder::new(&game_state).finish(Mode::ALL[0], 10);
+        for _ in 0..3 {
+            replay.store(&dir).unwrap();
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        assert_eq!(Replay::load_all(&dir).len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
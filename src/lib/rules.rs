@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+// gravity is measured in 1/256ths of a row per frame, so 256 is 1G and 5120 is 20G
+pub const G: u32 = 256;
+pub const MASTER_MAX_LEVEL: u64 = 999;
+pub const MASTER_SECTION_LEVELS: u64 = 100;
+
+// frames per row for each guideline level, bottoming out at one row a frame
+const GUIDELINE_FRAMES: [u32; 13] = [60, 48, 37, 28, 21, 16, 11, 8, 6, 4, 3, 2, 1];
+const MASTER_GRAVITY: [(u64, u32); 30] = [
+    (0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48), (90, 64), (100, 80),
+    (120, 96), (140, 112), (160, 128), (170, 144), (200, 4), (220, 32), (230, 64), (233, 96), (236, 128), (239, 160),
+    (243, 192), (247, 224), (251, 256), (300, 512), (330, 768), (360, 1024), (400, 1280), (420, 1024), (450, 768), (500, 20 * G),
+];
+// are and lock delay in frames for each hundred levels of a master game
+const MASTER_DELAYS: [(u32, u32); 10] = [(25, 30), (25, 30), (25, 30), (25, 30), (25, 30), (16, 30), (12, 26), (12, 22), (6, 18), (6, 15)];
+const MASTER_GRADES: [(u64, &str); 18] = [
+    (120_000, "S9"), (100_000, "S8"), (82_000, "S7"), (66_000, "S6"), (52_000, "S5"), (40_000, "S4"),
+    (30_000, "S3"), (22_000, "S2"), (16_000, "S1"), (12_000, "1"), (8_000, "2"), (5_500, "3"),
+    (3_500, "4"), (2_000, "5"), (1_400, "6"), (800, "7"), (400, "8"), (0, "9"),
+];
+// levels that must be reached within the given milliseconds for the grand master grade
+const GM_TIMES: [(u64, u64); 3] = [(300, 255_000), (500, 450_000), (999, 810_000)];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Speed {
+    Guideline,
+    Master,
+    Zen { gravity: bool },
+}
+
+#[derive(Clone, Copy)]
+pub struct Timing {
+    pub gravity: u32,
+    pub are: u32,
+    pub lock_delay: u32,
+    pub lock_resets: u32,
+}
+
+impl Timing {
+    // soft drop speeds up at least the slowest guideline gravity, so it still works in zen without gravity
+    pub fn soft_drop(self, factor: u64) -> u32 {
+        self.gravity.max(G / GUIDELINE_FRAMES[0]) * factor as u32
+    }
+}
+
+impl Speed {
+    pub fn timing(self, level: u64) -> Timing {
+        match self {
+            Speed::Guideline => {
+                let index = (level.max(1) - 1).min(GUIDELINE_FRAMES.len() as u64 - 1) as usize;
+                Timing {
+                    gravity: G / GUIDELINE_FRAMES[index],
+                    are: 6,
+                    lock_delay: 30,
+                    lock_resets: 15,
+                }
+            }
+            Speed::Master => {
+                let gravity = MASTER_GRAVITY.iter()
+                    .rev()
+                    .find(|&&(from, _)| level >= from)
+                    .map_or(4, |&(_, gravity)| gravity);
+                let section = (level / MASTER_SECTION_LEVELS).min(MASTER_DELAYS.len() as u64 - 1) as usize;
+                let (are, lock_delay) = MASTER_DELAYS[section];
+                Timing {
+                    gravity,
+                    are,
+                    lock_delay,
+                    lock_resets: 0,
+                }
+            }
+            // zen never speeds up and lets a grounded piece be moved for as long as the player likes
+            Speed::Zen { gravity } => Timing {
+                gravity: if gravity { G / GUIDELINE_FRAMES[0] } else { 0 },
+                are: 6,
+                lock_delay: 30,
+                lock_resets: u32::MAX,
+            },
+        }
+    }
+}
+
+// section_times holds the elapsed milliseconds at which each hundred levels was passed
+pub fn master_grade(score: u64, level: u64, section_times: &[u64]) -> &'static str {
+    let grade = MASTER_GRADES.iter()
+        .find(|&&(threshold, _)| score >= threshold)
+        .map_or("9", |&(_, grade)| grade);
+    let in_time = GM_TIMES.iter().all(|&(at, limit)| {
+        let index = (at / MASTER_SECTION_LEVELS) as usize;
+        let reached = if at == MASTER_MAX_LEVEL { section_times.get(index) } else { section_times.get(index - 1) };
+        reached.is_some_and(|&ms| ms <= limit)
+    });
+    if grade == "S9" && level >= MASTER_MAX_LEVEL && in_time {
+        "GM"
+    } else {
+        grade
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{GameState, Mode};
+
+// bumped whenever the saved game changes; fields added since an older save was written take their
+// defaults, and a save that still can't be read says which version it came from
+const SAVE_VERSION: u32 = 17;
+
+#[derive(Deserialize)]
+pub struct SaveFile {
+    pub mode: Mode,
+    pub game_state: GameState,
+    pub score: u64,
+}
+
+#[derive(Serialize)]
+struct SaveFileRef<'a> {
+    version: u32,
+    mode: Mode,
+    game_state: &'a GameState,
+    score: u64,
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl SaveFile {
+    pub fn load(path: &Path) -> Result<Option<SaveFile>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let header: SaveHeader = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        if header.version > SAVE_VERSION {
+            return Err(format!("save file version {} is newer than this game (expected {})", header.version, SAVE_VERSION));
+        }
+
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("save file version {} can't be read: {}", header.version, e))
+    }
+
+    pub fn store(path: &Path, mode: Mode, game_state: &GameState, score: u64) -> Result<(), String> {
+        let save = SaveFileRef {
+            version: SAVE_VERSION,
+            mode,
+            game_state,
+            score,
+        };
+        let contents = serde_json::to_string(&save).map_err(|e| e.to_string())?;
+
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents).map_err(|e| e.to_string())?;
+        fs::rename(&temp, path).map_err(|e| e.to_string())
+    }
+
+    pub fn remove(path: &Path) -> Result<(), String> {
+        if path.exists() {
+            fs::remove_file(path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("tetris-save-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn stored_games_load_back() {
+        let path = temp_path("round-trip");
+        SaveFile::store(&path, Mode::ALL[0], &GameState::default(), 1200).unwrap();
+        let save = SaveFile::load(&path).unwrap().unwrap();
+        assert_eq!(save.score, 1200);
+        assert_eq!(save.mode, Mode::ALL[0]);
+        SaveFile::remove(&path).unwrap();
+        assert!(SaveFile::load(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn older_saves_missing_fields_still_load() {
+        let path = temp_path("older");
+        SaveFile::store(&path, Mode::ALL[0], &GameState::default(), 300).unwrap();
+        let mut json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        json["version"] = Value::from(SAVE_VERSION - 1);
+        // everything added to the game after the board, the seats and the counts
+        let game_state = json["game_state"].as_object_mut().unwrap();
+        game_state.retain(|field, _| ["board", "turns", "seats", "cleared", "pieces"].contains(&field.as_str()));
+        for seat in game_state["seats"].as_array_mut().unwrap() {
+            seat.as_object_mut().unwrap().remove("keys");
+        }
+        fs::write(&path, json.to_string()).unwrap();
+
+        let save = SaveFile::load(&path).unwrap().unwrap();
+        assert_eq!(save.score, 300);
+        assert_eq!(save.game_state.players(), 1);
+        assert!(!save.game_state.finished());
+        SaveFile::remove(&path).unwrap();
+    }
+
+    #[test]
+    fn newer_saves_are_refused() {
+        let path = temp_path("newer");
+        SaveFile::store(&path, Mode::ALL[0], &GameState::default(), 0).unwrap();
+        let mut json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        json["version"] = Value::from(SAVE_VERSION + 1);
+        fs::write(&path, json.to_string()).unwrap();
+
+        assert!(SaveFile::load(&path).is_err());
+        SaveFile::remove(&path).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::lib::{Action, Board, PlayerPiece};
+
+// somewhere the piece can lock, with the actions that take it there from where it started, ending in
+// a hard drop; spun means the last move before the drop was a rotation the piece didn't fall out of,
+// which is what a t-spin needs
+#[derive(Clone)]
+pub struct Placement {
+    pub piece: PlayerPiece,
+    pub path: Vec<Action>,
+    pub spun: bool,
+}
+
+impl Placement {
+    // how many presses the path takes, with a run of soft drops being one held key
+    pub fn keys(&self) -> u64 {
+        self.path.iter()
+            .enumerate()
+            .filter(|&(i, &action)| action != Action::SoftDrop || i == 0 || self.path[i - 1] != Action::SoftDrop)
+            .count() as u64
+    }
+}
+
+// every distinct place the piece can lock from where it is, reached with moves, rotations and single
+// cell soft drops so tucks and spins turn up as well as straight drops. a spot that can be reached both
+// with and without a spin shows up once for each, and every path is as short as it can be
+pub fn reachable(piece: &PlayerPiece, board: &Board) -> Vec<Placement> {
+    let mut seen = HashSet::new();
+    let mut landed = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut found = vec![];
+    seen.insert(key(piece, false));
+    queue.push_back((piece.clone(), vec![]));
+
+    while let Some((piece, path)) = queue.pop_front() {
+        let resting = piece.go_down(board).is_none();
+        let spun = resting && path.last() == Some(&Action::Rotate);
+        let dropped = piece.lowest_possible_position(board);
+        if landed.insert((cells(&dropped), spun)) {
+            let mut path = path.clone();
+            path.push(Action::HardDrop);
+            found.push(Placement { piece: dropped, path, spun });
+        }
+
+        for &action in [Action::MoveLeft, Action::MoveRight, Action::Rotate, Action::SoftDrop].iter() {
+            let next = match action {
+                Action::MoveLeft => piece.go_left(board),
+                Action::MoveRight => piece.go_right(board),
+                Action::Rotate => piece.rotate(board),
+                _ => piece.go_down(board),
+            };
+            if let Some(next) = next {
+                // a rotation that ends up where a move already got to is still worth following, for the spin
+                if seen.insert(key(&next, action == Action::Rotate)) {
+                    let mut path = path.clone();
+                    path.push(action);
+                    queue.push_back((next, path));
+                }
+            }
+        }
+    }
+    found
+}
+
+fn key(piece: &PlayerPiece, rotated: bool) -> (i32, i32, usize, bool) {
+    (piece.anchor().x(), piece.anchor().y(), piece.rotation(), rotated)
+}
+
+fn cells(piece: &PlayerPiece) -> Vec<(i32, i32)> {
+    let mut cells: Vec<(i32, i32)> = piece.get_tiles().iter().map(|p| (p.x(), p.y())).collect();
+    cells.sort();
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{Cell, PieceSet};
+
+    #[test]
+    fn a_run_of_soft_drops_is_one_key() {
+        let piece = PieceSet::standard().find('O').unwrap();
+        let path = vec![Action::SoftDrop, Action::SoftDrop, Action::MoveLeft, Action::SoftDrop, Action::HardDrop];
+        assert_eq!(Placement { piece, path, spun: false }.keys(), 4);
+    }
+
+    #[test]
+    fn t_spins_are_found_spun() {
+        // a t slot with its overhang on the right, which only a rotation gets into
+        let rows = ["XX___XXXXX", "XXX_XXXXXX"];
+        let mut board = Board::new(10, 20);
+        board.place(4, 17, Cell::Garbage, 0);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == 'X' {
+                    board.place(x, 18 + y, Cell::Garbage, 0);
+                }
+            }
+        }
+        let t = PieceSet::standard().find('T').unwrap();
+        let slot = vec![(2, 18), (3, 18), (3, 19), (4, 18)];
+        let spins: Vec<Placement> = reachable(&t, &board).into_iter().filter(|p| {
+            let mut cells: Vec<(i32, i32)> = p.piece.get_tiles().iter().map(|t| (t.x(), t.y())).collect();
+            cells.sort();
+            cells == slot
+        }).collect();
+        assert!(!spins.is_empty());
+        assert!(spins.iter().all(|p| p.spun));
+        assert!(spins.iter().all(|p| p.path.last() == Some(&Action::HardDrop)));
+    }
+}
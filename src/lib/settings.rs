@@ -0,0 +1,589 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Action, AttackTable, BotWeights, HolePattern, StatField};
+
+pub const SETTINGS_PATH: &str = "settings.toml";
+pub const MAX_PREVIEW_COUNT: usize = 6;
+pub const MAX_FIRST_TO: u64 = 9;
+pub const MAX_BOT_DELAY: u32 = 30;
+// these always pause, so they can't be bound to anything else
+pub const PAUSE_KEYS: [Keycode; 2] = [Keycode::P, Keycode::Escape];
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub video: VideoSettings,
+    pub audio: AudioSettings,
+    pub handling: HandlingSettings,
+    pub keys: KeyBindings,
+    pub theme: Theme,
+    pub gameplay: GameplaySettings,
+    pub garbage: GarbageSettings,
+    pub versus: VersusSettings,
+    pub bot: BotSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoSettings {
+    pub width: u32,
+    pub height: u32,
+    pub scale: f32,
+    pub font: String,
+    pub font_size: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    pub frequency: i32,
+    pub channels: i32,
+    pub chunk_size: i32,
+    pub master_volume: u8,
+    pub effects_volume: u8,
+}
+
+// das and arr are in frames, sdf multiplies gravity while soft dropping, or a slow fall where there is none
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HandlingSettings {
+    pub das: u32,
+    pub arr: u32,
+    pub sdf: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub move_left: Vec<Key>,
+    pub move_right: Vec<Key>,
+    pub soft_drop: Vec<Key>,
+    pub hard_drop: Vec<Key>,
+    pub rotate: Vec<Key>,
+    pub hold: Vec<Key>,
+    pub reveal: Vec<Key>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub background: ThemeColor,
+    pub board: ThemeColor,
+    pub piece: ThemeColor,
+    pub ghost: ThemeColor,
+    pub grid: ThemeColor,
+    pub danger: ThemeColor,
+    pub text: ThemeColor,
+    pub garbage: ThemeColor,
+    pub flash: ThemeColor,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameplaySettings {
+    pub ghost: bool,
+    pub preview_count: usize,
+    pub stats: Vec<StatField>,
+    pub visibility: Visibility,
+    pub fade_seconds: u32,
+    pub piece_set: String,
+}
+
+// fading blocks disappear fade_seconds after they lock, invisible ones as soon as the lock flash is over
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Visible,
+    Fading,
+    Invisible,
+}
+
+// messiness is the percentage chance that the hole moves from one messy garbage row to the next,
+// and delay is how many frames incoming garbage waits before it can rise
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GarbageSettings {
+    pub holes: usize,
+    pub messiness: u32,
+    pub pattern: HolePattern,
+    pub delay: u32,
+    pub attack: AttackTable,
+}
+
+// the second player's keys, for co-op as well as versus; the first player uses the normal bindings
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct VersusSettings {
+    pub first_to: u64,
+    pub keys: KeyBindings,
+}
+
+// when enabled the bot plays as the first player in every mode; lookahead is how many preview pieces it
+// plans with, delay is the frames between its inputs and demo_seconds how long the title screen waits
+// before the bot starts a demo game. command, when given, is a program and its arguments to play instead
+// of the built in bot, speaking the tetris bot protocol over its stdin and stdout
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotSettings {
+    pub enabled: bool,
+    pub lookahead: usize,
+    pub delay: u32,
+    pub demo_seconds: u32,
+    pub command: Vec<String>,
+    pub weights: BotWeights,
+}
+
+// everything a key can be bound to: the game's actions, and holding it to see a hidden stack
+#[derive(Clone, Copy, PartialEq)]
+pub enum Binding {
+    Action(Action),
+    Reveal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Key(pub Keycode);
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct ThemeColor(pub Color);
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Settings, String> {
+        if !path.exists() {
+            let settings = Settings::default();
+            settings.store(path)?;
+            return Ok(settings);
+        }
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let settings: Settings = toml::from_str(&contents)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        settings.validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(settings)
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), String> {
+        let contents = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn volume(&self) -> i32 {
+        let max = sdl2::mixer::MAX_VOLUME as u32;
+        (max * self.audio.master_volume as u32 * self.audio.effects_volume as u32 / 10_000) as i32
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        check_range("video.width", self.video.width, 200, 7680)?;
+        check_range("video.height", self.video.height, 200, 4320)?;
+        if !(self.video.scale > 0.0 && self.video.scale <= 8.0) {
+            return Err(format!("video.scale must be greater than 0 and at most 8 (got {})", self.video.scale));
+        }
+        check_range("video.font_size", self.video.font_size, 8, 256)?;
+        check_range("audio.frequency", self.audio.frequency, 8000, 192_000)?;
+        check_range("audio.channels", self.audio.channels, 1, 2)?;
+        check_range("audio.chunk_size", self.audio.chunk_size, 256, 8192)?;
+        check_range("audio.master_volume", self.audio.master_volume, 0, 100)?;
+        check_range("audio.effects_volume", self.audio.effects_volume, 0, 100)?;
+        check_range("handling.das", self.handling.das, 0, 60)?;
+        check_range("handling.arr", self.handling.arr, 0, 60)?;
+        check_range("handling.sdf", self.handling.sdf, 1, 100)?;
+        check_range("gameplay.preview_count", self.gameplay.preview_count, 0, MAX_PREVIEW_COUNT)?;
+        check_range("gameplay.fade_seconds", self.gameplay.fade_seconds, 1, 30)?;
+        check_range("garbage.holes", self.garbage.holes, 1, 9)?;
+        check_range("garbage.messiness", self.garbage.messiness, 0, 100)?;
+        check_range("garbage.delay", self.garbage.delay, 0, 300)?;
+        check_range("versus.first_to", self.versus.first_to, 1, MAX_FIRST_TO)?;
+        check_range("bot.lookahead", self.bot.lookahead, 0, 3)?;
+        check_range("bot.delay", self.bot.delay, 0, MAX_BOT_DELAY)?;
+        check_range("bot.demo_seconds", self.bot.demo_seconds, 5, 600)?;
+        // a key can only do one thing, for either player
+        let mut bound: Vec<(Key, String)> = vec![];
+        for &(section, keys) in [("keys", &self.keys), ("versus.keys", &self.versus.keys)].iter() {
+            for &binding in Binding::ALL.iter() {
+                let name = format!("{}.{}", section, binding.name());
+                if keys.keys(binding).is_empty() {
+                    return Err(format!("{} needs at least one key", name));
+                }
+                for &key in keys.keys(binding) {
+                    if PAUSE_KEYS.contains(&key.0) {
+                        return Err(format!("{} pauses the game, so it can't be {}", String::from(key), name));
+                    }
+                    if let Some((_, first)) = bound.iter().find(|(k, _)| *k == key) {
+                        return Err(format!("{} is bound to both {} and {}", String::from(key), first, name));
+                    }
+                    bound.push((key, name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KeyBindings {
+    pub fn get(&self, action: Action) -> &[Key] {
+        match action {
+            Action::MoveLeft => &self.move_left,
+            Action::MoveRight => &self.move_right,
+            Action::SoftDrop => &self.soft_drop,
+            Action::HardDrop => &self.hard_drop,
+            Action::Rotate => &self.rotate,
+            Action::Hold => &self.hold,
+        }
+    }
+
+    pub fn action(&self, keycode: Keycode) -> Option<Action> {
+        Action::ALL.iter()
+            .cloned()
+            .find(|&action| self.get(action).contains(&Key(keycode)))
+    }
+
+    pub fn reveals(&self, keycode: Keycode) -> bool {
+        self.reveal.contains(&Key(keycode))
+    }
+
+    pub fn keys(&self, binding: Binding) -> &[Key] {
+        match binding {
+            Binding::Action(action) => self.get(action),
+            Binding::Reveal => &self.reveal,
+        }
+    }
+
+    fn binding(&self, keycode: Keycode) -> Option<Binding> {
+        Binding::ALL.iter()
+            .cloned()
+            .find(|&binding| self.keys(binding).contains(&Key(keycode)))
+    }
+
+    // the key moves away from whatever it did before, unless it's the only key for that or it belongs
+    // to the other player, in which case nothing changes and the reason comes back
+    pub fn rebind(&mut self, binding: Binding, keycode: Keycode, other: &KeyBindings) -> Result<(), String> {
+        if PAUSE_KEYS.contains(&keycode) {
+            return Err(format!("{} pauses the game", keycode.name()));
+        }
+        if let Some(taken) = other.binding(keycode) {
+            return Err(format!("{} is player 2's {} key", keycode.name(), taken.name()));
+        }
+        if let Some(current) = self.binding(keycode).filter(|&b| b != binding && self.keys(b).len() == 1) {
+            return Err(format!("{} is the only {} key", keycode.name(), current.name()));
+        }
+        for &other in Binding::ALL.iter() {
+            self.keys_mut(other).retain(|&k| k != Key(keycode));
+        }
+        self.keys_mut(binding).insert(0, Key(keycode));
+        Ok(())
+    }
+
+    fn keys_mut(&mut self, binding: Binding) -> &mut Vec<Key> {
+        match binding {
+            Binding::Action(Action::MoveLeft) => &mut self.move_left,
+            Binding::Action(Action::MoveRight) => &mut self.move_right,
+            Binding::Action(Action::SoftDrop) => &mut self.soft_drop,
+            Binding::Action(Action::HardDrop) => &mut self.hard_drop,
+            Binding::Action(Action::Rotate) => &mut self.rotate,
+            Binding::Action(Action::Hold) => &mut self.hold,
+            Binding::Reveal => &mut self.reveal,
+        }
+    }
+}
+
+impl Binding {
+    pub const ALL: [Binding; 7] = [
+        Binding::Action(Action::MoveLeft),
+        Binding::Action(Action::MoveRight),
+        Binding::Action(Action::SoftDrop),
+        Binding::Action(Action::HardDrop),
+        Binding::Action(Action::Rotate),
+        Binding::Action(Action::Hold),
+        Binding::Reveal,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Binding::Action(action) => action.name(),
+            Binding::Reveal => "reveal",
+        }
+    }
+}
+
+impl GameplaySettings {
+    // the number of frames a locked block stays on screen, if it ever goes
+    pub fn fade_frames(&self) -> Option<i64> {
+        match self.visibility {
+            Visibility::Visible => None,
+            Visibility::Fading => Some(self.fade_seconds as i64 * 60),
+            Visibility::Invisible => Some(0),
+        }
+    }
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            width: 800,
+            height: 800,
+            scale: 2.0,
+            font: "DroidSansMono.ttf".to_string(),
+            font_size: 64,
+        }
+    }
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            frequency: 44100,
+            channels: 2,
+            chunk_size: 1024,
+            master_volume: 100,
+            effects_volume: 100,
+        }
+    }
+}
+
+impl Default for HandlingSettings {
+    fn default() -> Self {
+        HandlingSettings {
+            das: 10,
+            arr: 2,
+            sdf: 20,
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            move_left: vec![Key(Keycode::A), Key(Keycode::Left)],
+            move_right: vec![Key(Keycode::D), Key(Keycode::Right)],
+            soft_drop: vec![Key(Keycode::S), Key(Keycode::Down)],
+            hard_drop: vec![Key(Keycode::W), Key(Keycode::Up)],
+            rotate: vec![Key(Keycode::R), Key(Keycode::Space)],
+            hold: vec![Key(Keycode::Q)],
+            reveal: vec![Key(Keycode::Tab)],
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            background: ThemeColor(Color::RGB(0, 0, 0)),
+            board: ThemeColor(Color::RGB(255, 255, 0)),
+            piece: ThemeColor(Color::RGB(128, 50, 200)),
+            ghost: ThemeColor(Color::RGB(0, 255, 255)),
+            grid: ThemeColor(Color::RGB(0, 0, 255)),
+            danger: ThemeColor(Color::RGB(255, 0, 0)),
+            text: ThemeColor(Color::RGB(255, 0, 0)),
+            garbage: ThemeColor(Color::RGB(128, 128, 128)),
+            flash: ThemeColor(Color::RGB(255, 255, 255)),
+        }
+    }
+}
+
+impl Default for GameplaySettings {
+    fn default() -> Self {
+        GameplaySettings {
+            ghost: true,
+            preview_count: 1,
+            stats: StatField::ALL.to_vec(),
+            visibility: Visibility::Visible,
+            fade_seconds: 5,
+            piece_set: "pieces/tetromino.toml".to_string(),
+        }
+    }
+}
+
+impl Default for GarbageSettings {
+    fn default() -> Self {
+        GarbageSettings {
+            holes: 1,
+            messiness: 30,
+            pattern: HolePattern::Messy,
+            delay: 30,
+            attack: AttackTable::default(),
+        }
+    }
+}
+
+impl Default for VersusSettings {
+    fn default() -> Self {
+        VersusSettings {
+            first_to: 3,
+            keys: KeyBindings {
+                move_left: vec![Key(Keycode::J)],
+                move_right: vec![Key(Keycode::L)],
+                soft_drop: vec![Key(Keycode::M)],
+                hard_drop: vec![Key(Keycode::K)],
+                rotate: vec![Key(Keycode::I)],
+                hold: vec![Key(Keycode::U)],
+                reveal: vec![Key(Keycode::O)],
+            },
+        }
+    }
+}
+
+impl Default for BotSettings {
+    fn default() -> Self {
+        BotSettings {
+            enabled: false,
+            lookahead: 1,
+            delay: 4,
+            demo_seconds: 30,
+            command: vec![],
+            weights: BotWeights::default(),
+        }
+    }
+}
+
+impl TryFrom<String> for Key {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Keycode::from_name(&name)
+            .map(Key)
+            .ok_or_else(|| format!("unknown key name '{}'", name))
+    }
+}
+
+impl From<Key> for String {
+    fn from(key: Key) -> Self {
+        key.0.name()
+    }
+}
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(hex: String) -> Result<Self, Self::Error> {
+        let digits = hex.trim_start_matches('#');
+        if digits.len() != 6 {
+            return Err(format!("colour '{}' should look like #rrggbb", hex));
+        }
+        let value = u32::from_str_radix(digits, 16)
+            .map_err(|_| format!("colour '{}' should look like #rrggbb", hex))?;
+        Ok(ThemeColor(Color::RGB((value >> 16) as u8, (value >> 8) as u8, value as u8)))
+    }
+}
+
+impl From<ThemeColor> for String {
+    fn from(color: ThemeColor) -> Self {
+        format!("#{:02x}{:02x}{:02x}", color.0.r, color.0.g, color.0.b)
+    }
+}
+
+fn check_range<T: PartialOrd + std::fmt::Display>(name: &str, value: T, min: T, max: T) -> Result<(), String> {
+    if value < min || value > max {
+        Err(format!("{} must be between {} and {} (got {})", name, min, max, value))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid_and_round_trip() {
+        let settings = Settings::default();
+        assert!(settings.validate().is_ok());
+        let text = toml::to_string_pretty(&settings).unwrap();
+        let parsed: Settings = toml::from_str(&text).unwrap();
+        assert!(parsed.validate().is_ok());
+    }
+
+    #[test]
+    fn misspelled_fields_are_refused() {
+        let error = toml::from_str::<Settings>("[handling]\ndass = 4\n").err().unwrap().to_string();
+        assert!(error.contains("dass"), "{}", error);
+        assert!(toml::from_str::<Settings>("[video]\nwidht = 800\n").is_err());
+    }
+
+    #[test]
+    fn values_out_of_range_are_refused() {
+        let mut settings = Settings::default();
+        settings.handling.sdf = 0;
+        assert_eq!(settings.validate().err().unwrap(), "handling.sdf must be between 1 and 100 (got 0)");
+    }
+
+    #[test]
+    fn every_binding_needs_a_key() {
+        let mut settings = Settings::default();
+        settings.keys.reveal.clear();
+        assert_eq!(settings.validate().err().unwrap(), "keys.reveal needs at least one key");
+    }
+
+    #[test]
+    fn keys_bound_twice_are_refused() {
+        let mut settings = Settings::default();
+        settings.keys.hold.push(Key(Keycode::S));
+        assert_eq!(settings.validate().err().unwrap(), "S is bound to both keys.soft_drop and keys.hold");
+
+        let mut settings = Settings::default();
+        settings.versus.keys.rotate.push(Key(Keycode::Q));
+        assert_eq!(settings.validate().err().unwrap(), "Q is bound to both keys.hold and versus.keys.rotate");
+    }
+
+    #[test]
+    fn rebinding_moves_a_key_from_its_old_action() {
+        let mut settings = Settings::default();
+        let other = settings.versus.keys.clone();
+        settings.keys.rebind(Binding::Action(Action::Hold), Keycode::Down, &other).unwrap();
+        assert_eq!(settings.keys.action(Keycode::Down), Some(Action::Hold));
+        assert!(!settings.keys.soft_drop.contains(&Key(Keycode::Down)));
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn rebinding_an_only_key_is_refused() {
+        let mut settings = Settings::default();
+        let other = settings.versus.keys.clone();
+        let error = settings.keys.rebind(Binding::Action(Action::SoftDrop), Keycode::Q, &other).err().unwrap();
+        assert_eq!(error, "Q is the only hold key");
+        assert_eq!(settings.keys.action(Keycode::Q), Some(Action::Hold));
+    }
+
+    #[test]
+    fn rebinding_the_other_players_key_is_refused() {
+        let mut settings = Settings::default();
+        let other = settings.versus.keys.clone();
+        assert!(settings.keys.rebind(Binding::Action(Action::Rotate), Keycode::K, &other).is_err());
+        assert_eq!(settings.keys.action(Keycode::K), None);
+    }
+
+    #[test]
+    fn pause_keys_cant_be_bound() {
+        let mut settings = Settings::default();
+        let other = settings.versus.keys.clone();
+        assert_eq!(settings.keys.rebind(Binding::Action(Action::Hold), Keycode::P, &other).err().unwrap(), "P pauses the game");
+        assert_eq!(settings.keys.action(Keycode::P), None);
+
+        settings.versus.keys.hold.push(Key(Keycode::Escape));
+        assert_eq!(settings.validate().err().unwrap(), "Escape pauses the game, so it can't be versus.keys.hold");
+    }
+
+    #[test]
+    fn reveal_can_be_rebound() {
+        let mut settings = Settings::default();
+        let other = settings.versus.keys.clone();
+        settings.keys.rebind(Binding::Reveal, Keycode::E, &other).unwrap();
+        assert!(settings.keys.reveals(Keycode::E));
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn colours_parse_from_hex() {
+        let color = ThemeColor::try_from("#ff8000".to_string()).unwrap();
+        assert_eq!((color.0.r, color.0.g, color.0.b), (255, 128, 0));
+        assert!(ThemeColor::try_from("orange".to_string()).is_err());
+        assert_eq!(String::from(color), "#ff8000");
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+use crate::lib::AttackTable;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum StatField {
+    Lines,
+    Level,
+    Time,
+    Pieces,
+    Pps,
+    Kpp,
+    Apm,
+    Clears,
+    TSpins,
+    MaxCombo,
+    Finesse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Stats {
+    pub keys: u64,
+    pub attack: u64,
+    pub clears: [u64; 4],
+    pub t_spins: u64,
+    pub max_combo: u64,
+    pub finesse_faults: u64,
+    combo: Option<u64>,
+    back_to_back: bool,
+}
+
+impl StatField {
+    pub const ALL: [StatField; 11] = [
+        StatField::Lines,
+        StatField::Level,
+        StatField::Time,
+        StatField::Pieces,
+        StatField::Pps,
+        StatField::Kpp,
+        StatField::Apm,
+        StatField::Clears,
+        StatField::TSpins,
+        StatField::MaxCombo,
+        StatField::Finesse,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StatField::Lines => "Lines",
+            StatField::Level => "Level",
+            StatField::Time => "Time",
+            StatField::Pieces => "Pieces",
+            StatField::Pps => "PPS",
+            StatField::Kpp => "KPP",
+            StatField::Apm => "APM",
+            StatField::Clears => "Clears",
+            StatField::TSpins => "T-spins",
+            StatField::MaxCombo => "Max combo",
+            StatField::Finesse => "Finesse faults",
+        }
+    }
+}
+
+impl Stats {
+    pub fn combo(&self) -> u64 {
+        self.combo.unwrap_or(0)
+    }
+
+    pub fn back_to_back(&self) -> bool {
+        self.back_to_back
+    }
+
+    // called once per locked piece; a fault is a piece placed with more presses than it needed. returns the attack
+    pub fn lock(&mut self, lines: usize, t_spin: bool, perfect_clear: bool, fault: bool, table: &AttackTable) -> u64 {
+        if fault {
+            self.finesse_faults += 1;
+        }
+
+        if t_spin {
+            self.t_spins += 1;
+        }
+        if lines == 0 {
+            self.combo = None;
+            return 0;
+        }
+
+        self.clears[lines.min(4) - 1] += 1;
+        let combo = self.combo.map_or(0, |c| c + 1);
+        self.combo = Some(combo);
+        self.max_combo = self.max_combo.max(combo);
+
+        let difficult = lines >= 4 || t_spin;
+        let attack = table.attack(lines, t_spin, difficult && self.back_to_back, combo, perfect_clear);
+        self.back_to_back = difficult;
+        self.attack += attack;
+        attack
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::iter;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lib::{Plan, Position};
+
+// protocol boards are always this tall, listed from the floor up
+const ROWS: usize = 40;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules,
+    Start {
+        hold: Option<char>,
+        queue: Vec<char>,
+        combo: u64,
+        back_to_back: bool,
+        board: Vec<Vec<Option<char>>>,
+    },
+    Suggest,
+    Stop,
+    Quit,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info {},
+    Ready {},
+    Error { reason: String },
+    Suggestion { moves: Vec<Move> },
+}
+
+#[derive(Deserialize)]
+struct Move {
+    location: Location,
+    spin: String,
+}
+
+// x and y are the piece's centre, counted from the left wall and the floor
+#[derive(Deserialize)]
+struct Location {
+    #[serde(rename = "type")]
+    piece: char,
+    orientation: Orientation,
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+// protocol pieces are the seven tetrominoes, named by these letters
+const PIECES: &str = "IOTLJSZ";
+
+// a bot running as its own process and speaking the tetris bot protocol. the game's hold swaps with the
+// next piece rather than keeping one aside, so instead of following the bot's idea of the queue every
+// piece starts the bot afresh, with the piece a hold would swap in sent as the held one; either way its
+// hold works out the same as ours for one move
+pub struct ExternalBot {
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    messages: Receiver<BotMessage>,
+    ready: bool,
+    started: bool,
+    waiting: Option<(u64, Position)>,
+    asked: VecDeque<(u64, Position)>,
+}
+
+impl ExternalBot {
+    pub fn spawn(command: &[String]) -> Result<ExternalBot, String> {
+        let (program, args) = command.split_first().ok_or_else(|| "there is no command".to_string())?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().ok_or_else(|| "there is no stdout".to_string())?;
+
+        let (sender, messages) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("ignoring bot message {}: {}", line, e),
+                }
+            }
+        });
+
+        Ok(ExternalBot {
+            child: Some(child),
+            stdin,
+            messages,
+            ready: false,
+            started: false,
+            waiting: None,
+            asked: VecDeque::new(),
+        })
+    }
+
+    // whether every piece in the position is one the protocol has a name for
+    pub fn can_play(position: &Position) -> bool {
+        iter::once(&position.piece).chain(position.queue.iter())
+            .all(|p| PIECES.contains(p.name()) && p.base().len() == 4)
+    }
+
+    // asks for a move; until the bot is ready only the latest position is kept
+    pub fn request(&mut self, turn: u64, position: &Position) {
+        if self.ready {
+            self.start(turn, position);
+        } else {
+            self.waiting = Some((turn, position.clone()));
+        }
+    }
+
+    // the plans for every suggestion that has come in, with the turn each was asked for
+    pub fn plans(&mut self) -> Vec<(u64, Plan)> {
+        let mut plans = vec![];
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                BotMessage::Info {} => self.send(&FrontendMessage::Rules),
+                BotMessage::Ready {} => {
+                    self.ready = true;
+                    if let Some((turn, position)) = self.waiting.take() {
+                        self.start(turn, &position);
+                    }
+                }
+                BotMessage::Error { reason } => eprintln!("the bot stopped: {}", reason),
+                BotMessage::Suggestion { moves } => {
+                    if let Some((turn, position)) = self.asked.pop_front() {
+                        plans.push((turn, plan(&moves, &position)));
+                    }
+                }
+            }
+        }
+        plans
+    }
+
+    fn start(&mut self, turn: u64, position: &Position) {
+        if self.started {
+            self.send(&FrontendMessage::Stop);
+        }
+        self.started = true;
+
+        // the bot only needs to know which cells are filled, so everything goes down as garbage
+        let (width, height) = (position.board.width() as i32, position.board.height() as i32);
+        let board = (0..ROWS as i32)
+            .map(|row| {
+                (0..width)
+                    .map(|x| if row < height && !position.board.is_free(x, height - 1 - row) { Some('G') } else { None })
+                    .collect()
+            })
+            .collect();
+        let (hold, next) = match position.queue.split_first() {
+            Some((swap, next)) if position.hold => (Some(swap.name()), next),
+            _ => (None, &position.queue[..]),
+        };
+        self.send(&FrontendMessage::Start {
+            hold,
+            queue: iter::once(&position.piece).chain(next.iter()).map(|p| p.name()).collect(),
+            combo: position.combo,
+            back_to_back: position.back_to_back,
+            board,
+        });
+        self.send(&FrontendMessage::Suggest);
+        self.asked.push_back((turn, position.clone()));
+    }
+
+    fn send(&mut self, message: &FrontendMessage) {
+        if let Some(stdin) = &mut self.stdin {
+            let sent = serde_json::to_writer(&mut *stdin, message)
+                .map_err(|e| e.to_string())
+                .and_then(|_| writeln!(stdin).and_then(|_| stdin.flush()).map_err(|e| e.to_string()));
+            if let Err(e) = sent {
+                eprintln!("couldn't write to the bot: {}", e);
+                self.stdin = None;
+            }
+        }
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        self.send(&FrontendMessage::Quit);
+        self.stdin = None;
+        // waited on to the side so a bot that ignores quit can't hold up the game
+        if let Some(mut child) = self.child.take() {
+            thread::spawn(move || child.wait());
+        }
+    }
+}
+
+impl Location {
+    // the cells around the centre for a piece pointing north, turned clockwise into its orientation
+    // and flipped so y counts down from the top of the board like the game's does
+    fn cells(&self, height: i32) -> Vec<(i32, i32)> {
+        let north = match self.piece {
+            'I' => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+            'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            'T' => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+            'L' => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+            'J' => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+            'S' => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+            'Z' => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+            _ => return vec![],
+        };
+        let mut cells: Vec<(i32, i32)> = north.iter()
+            .map(|&(x, y)| match self.orientation {
+                Orientation::North => (x, y),
+                Orientation::East => (y, -x),
+                Orientation::South => (-x, -y),
+                Orientation::West => (-y, x),
+            })
+            .map(|(x, y)| (self.x + x, height - 1 - (self.y + y)))
+            .collect();
+        cells.sort();
+        cells
+    }
+}
+
+// the first suggestion the game can play, with the piece in play or the one a hold swaps in; when there
+// isn't one the piece just drops
+fn plan(moves: &[Move], position: &Position) -> Plan {
+    let held = position.queue.first().filter(|_| position.hold).map(|p| p.name());
+    let height = position.board.height() as i32;
+    moves.iter()
+        .find_map(|m| {
+            let hold = if m.location.piece == position.piece.name() {
+                false
+            } else if Some(m.location.piece) == held {
+                true
+            } else {
+                return None;
+            };
+            Some(Plan { hold, target: m.location.cells(height), spun: m.spin != "none", path: vec![] })
+        })
+        .unwrap_or(Plan { hold: false, target: vec![], spun: false, path: vec![] })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::lib::{Board, PieceSet};
+
+    fn location(piece: char, orientation: Orientation, x: i32, y: i32) -> Location {
+        Location { piece, orientation, x, y }
+    }
+
+    fn position(set: &PieceSet, piece: char, next: char, hold: bool) -> Position {
+        Position {
+            board: Board::new(10, 20),
+            piece: set.find(piece).unwrap(),
+            queue: vec![set.find(next).unwrap()],
+            hold,
+            combo: 0,
+            back_to_back: false,
+        }
+    }
+
+    #[test]
+    fn locations_turn_clockwise_and_count_down_from_the_top() {
+        assert_eq!(location('T', Orientation::North, 4, 0).cells(20), vec![(3, 19), (4, 18), (4, 19), (5, 19)]);
+        assert_eq!(location('T', Orientation::East, 4, 1).cells(20), vec![(4, 17), (4, 18), (4, 19), (5, 18)]);
+        assert_eq!(location('T', Orientation::South, 4, 1).cells(20), vec![(3, 18), (4, 18), (4, 19), (5, 18)]);
+        assert_eq!(location('T', Orientation::West, 4, 1).cells(20), vec![(3, 18), (4, 17), (4, 18), (4, 19)]);
+        assert_eq!(location('I', Orientation::West, 0, 1).cells(20), vec![(0, 16), (0, 17), (0, 18), (0, 19)]);
+        assert_eq!(location('O', Orientation::North, 8, 0).cells(4), vec![(8, 2), (8, 3), (9, 2), (9, 3)]);
+        assert!(location('G', Orientation::North, 4, 0).cells(20).is_empty());
+    }
+
+    #[test]
+    fn suggestions_for_the_swap_piece_hold_only_when_holding_is_allowed() {
+        let set = PieceSet::standard();
+        let moves = vec![
+            Move { location: location('Z', Orientation::North, 4, 0), spin: "none".to_string() },
+            Move { location: location('I', Orientation::North, 1, 0), spin: "none".to_string() },
+            Move { location: location('T', Orientation::South, 4, 1), spin: "full".to_string() },
+        ];
+
+        let held = plan(&moves, &position(&set, 'T', 'I', true));
+        assert!(held.hold);
+        assert_eq!(held.target, vec![(0, 19), (1, 19), (2, 19), (3, 19)]);
+        assert!(!held.spun);
+
+        let kept = plan(&moves, &position(&set, 'T', 'I', false));
+        assert!(!kept.hold);
+        assert_eq!(kept.target, vec![(3, 18), (4, 18), (4, 19), (5, 18)]);
+        assert!(kept.spun);
+    }
+
+    #[test]
+    fn only_tetrominoes_with_protocol_names_can_be_played() {
+        let standard = PieceSet::standard();
+        assert!(ExternalBot::can_play(&position(&standard, 'T', 'I', true)));
+
+        // named like tetrominoes, but the wrong size
+        let trominoes = PieceSet::load(Path::new("pieces/tromino.toml")).unwrap();
+        assert!(!ExternalBot::can_play(&position(&trominoes, 'I', 'I', true)));
+        let pentominoes = PieceSet::load(Path::new("pieces/pentomino.toml")).unwrap();
+        assert!(!ExternalBot::can_play(&position(&pentominoes, 'T', 'S', true)));
+        assert!(!ExternalBot::can_play(&position(&pentominoes, 'W', 'S', true)));
+    }
+}
@@ -0,0 +1,14 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+
+pub fn draw_text<'a>(canvas: &mut WindowCanvas, font: &Font<'a, 'static>, text: &str, color: Color, rect: Rect) -> Result<(), String> {
+    let surface = font.render(text)
+        .blended(color)
+        .map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator.create_texture_from_surface(surface)
+        .map_err(|e| e.to_string())?;
+    canvas.copy(&texture, None, Some(rect))
+}
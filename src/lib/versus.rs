@@ -0,0 +1,197 @@
+use std::sync::mpsc::SyncSender;
+
+use sdl2::event::Event;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use sdl2::ttf::Font;
+
+use crate::lib::{Bot, Controls, GameState, Settings, Sound};
+use crate::lib::text::draw_text;
+
+struct Player {
+    game_state: GameState,
+    controls: Controls,
+    wins: u64,
+}
+
+// when the bot is enabled it plays as player 1, so one person can play against it; player 1's keys then do nothing
+pub struct Versus {
+    players: Vec<Player>,
+    bot: Option<Bot>,
+    first_to: u64,
+    round: u64,
+    winner: Option<usize>,
+}
+
+impl Versus {
+    pub fn new(first_to: u64, settings: &Settings) -> Versus {
+        Versus {
+            players: vec![],
+            bot: if settings.bot.enabled { Some(Bot::new(settings)) } else { None },
+            first_to,
+            round: 0,
+            winner: None,
+        }
+    }
+
+    // every round starts both players from fresh boards, keeping the wins so far
+    pub fn next_round(&mut self, game_states: Vec<GameState>) {
+        let wins: Vec<u64> = self.players.iter().map(|p| p.wins).collect();
+        self.players = game_states.into_iter()
+            .enumerate()
+            .map(|(i, game_state)| Player { game_state, controls: Controls::new(), wins: wins.get(i).cloned().unwrap_or(0) })
+            .collect();
+        self.round += 1;
+        self.winner = None;
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn winner(&self) -> Option<usize> {
+        self.winner
+    }
+
+    pub fn champion(&self) -> Option<usize> {
+        self.players.iter().position(|p| p.wins >= self.first_to)
+    }
+
+    pub fn reset_controls(&mut self) {
+        for player in self.players.iter_mut() {
+            player.controls.reset();
+        }
+    }
+
+    pub fn handle(&mut self, event: &Event, settings: &Settings) -> bool {
+        let bindings = [&settings.keys, &settings.versus.keys];
+        let bot = self.bot.is_some();
+        match *event {
+            Event::KeyDown { keycode: Some(keycode), repeat, .. } => {
+                for (i, (player, keys)) in self.players.iter_mut().zip(bindings.iter()).enumerate() {
+                    if let Some(action) = keys.action(keycode) {
+                        // the bot's keys are still taken, so they don't reach the other player
+                        let botted = bot && i == 0;
+                        if !repeat && !botted {
+                            let action = player.controls.press(action);
+                            player.game_state.press(action);
+                        }
+                        return true;
+                    } else if keys.reveals(keycode) {
+                        player.controls.reveal(true);
+                        return true;
+                    }
+                }
+                false
+            }
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                for (player, keys) in self.players.iter_mut().zip(bindings.iter()) {
+                    if let Some(action) = keys.action(keycode) {
+                        player.controls.release(action);
+                    } else if keys.reveals(keycode) {
+                        player.controls.reveal(false);
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // returns true once the round is over; both players topping out on the same frame is a draw
+    pub fn update(&mut self, audio: &SyncSender<Sound>, settings: &Settings) -> bool {
+        if let (Some(bot), Some(player)) = (&mut self.bot, self.players.first_mut()) {
+            for action in bot.tick(&player.game_state) {
+                player.game_state.press(action);
+            }
+        }
+
+        let mut alive = vec![];
+        for player in self.players.iter_mut() {
+            for action in player.controls.tick(&settings.handling, player.game_state.board_width()) {
+                player.game_state.apply(action);
+            }
+            let soft_drop_factor = if player.controls.soft_dropping() { settings.handling.sdf as u64 } else { 1 };
+            alive.push(player.game_state.update(audio.clone(), soft_drop_factor).is_some());
+        }
+
+        let attacks: Vec<u64> = self.players.iter_mut().map(|p| p.game_state.take_attack()).collect();
+        let count = self.players.len();
+        for (i, attack) in attacks.into_iter().enumerate() {
+            self.players[(i + 1) % count].game_state.receive_garbage(attack);
+        }
+
+        if alive.iter().all(|&a| a) {
+            return false;
+        }
+        if alive.iter().filter(|&&a| a).count() == 1 {
+            self.winner = alive.iter().position(|&a| a);
+            if let Some(winner) = self.winner {
+                self.players[winner].wins += 1;
+            }
+        }
+        true
+    }
+
+    // each board is drawn at half size, side by side along the top of the window
+    pub fn draw<'a>(&self, canvas: &mut WindowCanvas, font: &Font<'a, 'static>, settings: &Settings, finished: bool) -> Result<(), String> {
+        let text = settings.theme.text.0;
+        let (scale_x, scale_y) = canvas.scale();
+        canvas.set_scale(scale_x / 2.0, scale_y / 2.0)?;
+        for (i, player) in self.players.iter().enumerate() {
+            canvas.set_viewport(Rect::new(800 * i as i32, 0, 800, 800));
+            player.game_state.draw(canvas, settings, finished || player.controls.revealing())?;
+            let lines = [
+                format!("Player {}", i + 1),
+                format!("Wins: {}", player.wins),
+                format!("Incoming: {}", player.game_state.incoming()),
+            ];
+            for (j, line) in lines.iter().enumerate() {
+                draw_text(canvas, font, line, text, Rect::new(450, 330 + 45 * j as i32, 16 * line.len() as u32, 40))?;
+            }
+        }
+        canvas.set_viewport(None);
+        canvas.set_scale(scale_x, scale_y)?;
+
+        let line = format!("Round {} - first to {}", self.round, self.first_to);
+        draw_text(canvas, font, &line, text, Rect::new(20, 420, 16 * line.len() as u32, 40))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use sdl2::keyboard::{Keycode, Mod};
+
+    use super::*;
+    use crate::lib::{Goal, PieceSet, Speed};
+
+    fn key_down(keycode: Keycode) -> Event {
+        Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::NOMOD, repeat: false }
+    }
+
+    #[test]
+    fn the_bots_board_ignores_player_ones_keys() {
+        let (audio, _sounds) = sync_channel(1000);
+        let mut settings = Settings::default();
+        settings.bot.enabled = true;
+        let mut versus = Versus::new(3, &settings);
+        let mut game_states = vec![];
+        for _ in 0..2 {
+            let mut game_state = GameState::new(Goal::None, Speed::Guideline, 1, &PieceSet::standard());
+            while game_state.position(0, 0).is_none() {
+                game_state.update(audio.clone(), 1);
+            }
+            game_states.push(game_state);
+        }
+        versus.next_round(game_states);
+
+        let first = settings.keys.hard_drop[0].0;
+        let second = settings.versus.keys.hard_drop[0].0;
+        assert!(versus.handle(&key_down(first), &settings));
+        assert!(versus.handle(&key_down(second), &settings));
+        assert_eq!(versus.players[0].game_state.stats().keys, 0);
+        assert_eq!(versus.players[1].game_state.stats().keys, 1);
+    }
+}
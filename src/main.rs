@@ -3,35 +3,41 @@ extern crate sdl2;
 use std::path::Path;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, SyncSender};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use sdl2::event::Event;
 use sdl2::mixer::AUDIO_S16LSB;
 use sdl2::pixels::Color;
 
-use crate::lib::{AppState, Sound};
+use tetris::lib::{AppState, Settings, SETTINGS_PATH, Sound};
 
-mod lib;
+// frames that can be caught up on at once; any more behind than this and the lost time is let go, so a
+// stall doesn't come back as a burst of fast play
+const MAX_CATCH_UP: u32 = 10;
 
 fn main() -> Result<(), String> {
+    let settings = Settings::load(Path::new(SETTINGS_PATH))?;
+
     let sdl_context = sdl2::init()?;
     let clear_sound = sdl2::mixer::Music::from_file(Path::new("sounds/clear.ogg"))?;
     let ground_sound = sdl2::mixer::Music::from_file(Path::new("sounds/ground.ogg"))?;
     let end_sound = sdl2::mixer::Music::from_file(Path::new("sounds/game_end.ogg"))?;
+    let warning_sound = sdl2::mixer::Music::from_file(Path::new("sounds/warning.wav"))?;
 
-    let frequency = 44100;
     let format = AUDIO_S16LSB; // signed 16 bit samples, in little-endian byte order
-    let channels = 2; // Stereo
-    let chunk_size = 1024;
-    sdl2::mixer::open_audio(frequency, format, channels, chunk_size)?;
+    let audio = &settings.audio;
+    sdl2::mixer::open_audio(audio.frequency, format, audio.channels, audio.chunk_size)?;
     sdl2::mixer::allocate_channels(2);
 
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
-    let font = ttf_context.load_font(Path::new("DroidSansMono.ttf"), 64)?;
+    let font = ttf_context.load_font(Path::new(&settings.video.font), settings.video.font_size)?;
 
     let mut event_pump = sdl_context.event_pump()?;
+    let controller_subsystem = sdl_context.game_controller()?;
+    let mut controllers = vec![];
     let video_subsystem = sdl_context.video()?;
     let window = video_subsystem
-        .window("tetrust", 800, 800)
+        .window("tetrust", settings.video.width, settings.video.height)
         .position_centered()
         .build()
         .or_else(|e| Err(e.to_string()))?;
@@ -39,31 +45,57 @@ fn main() -> Result<(), String> {
         .build()
         .or_else(|e| Err(e.to_string()))?;
 
-    canvas.set_scale(2.0, 2.0)?;
+    canvas.set_scale(settings.video.scale, settings.video.scale)?;
     canvas.set_draw_color(Color::RGB(0, 255, 255));
     canvas.clear();
     canvas.present();
 
-    let mut app_state = AppState::new();
+    let mut app_state = AppState::new(settings, video_subsystem.clipboard());
 
     let (a_send, a_recv): (SyncSender<Sound>, Receiver<Sound>) = mpsc::sync_channel(10);
 
+    // the game moves in fixed sixtieths of a second, running extra frames after a slow one, so its frame
+    // counted clocks keep up with real time
+    let frame = Duration::new(0, 1_000_000_000u32 / 60);
+    let mut next_frame = Instant::now();
     'running: loop {
-        if !event_pump.poll_iter().all(|e| app_state.handle(e)) {
-            break 'running;
+        for event in event_pump.poll_iter() {
+            if let Event::ControllerDeviceAdded { which, .. } = event {
+                match controller_subsystem.open(which) {
+                    Ok(controller) => controllers.push(controller),
+                    Err(e) => eprintln!("failed to open controller {}: {}", which, e),
+                }
+            }
+            if !app_state.handle(event) {
+                break 'running;
+            }
         }
 
-        app_state.update(a_send.clone());
+        let mut frames = 0;
+        while Instant::now() >= next_frame {
+            app_state.update(a_send.clone());
+            // played after every frame, as the channel only holds a few
+            while let Ok(s) = a_recv.try_recv() {
+                sdl2::mixer::Music::set_volume(app_state.volume());
+                match s {
+                    Sound::Clear => clear_sound.play(1)?,
+                    Sound::Ground => ground_sound.play(1)?,
+                    Sound::End => end_sound.play(1)?,
+                    Sound::Warning => warning_sound.play(1)?,
+                }
+            }
+            next_frame += frame;
+            frames += 1;
+            if frames == MAX_CATCH_UP {
+                next_frame = Instant::now() + frame;
+            }
+        }
         app_state.draw(&mut canvas, &font)?;
 
-        while let Ok(s) = a_recv.recv_timeout(Duration::from_millis(1)) {
-            match s {
-                Sound::Clear => clear_sound.play(1)?,
-                Sound::Ground => ground_sound.play(1)?,
-                Sound::End => end_sound.play(1)?,
-            }
+        let now = Instant::now();
+        if next_frame > now {
+            ::std::thread::sleep(next_frame - now);
         }
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
 
     Ok(())
@@ -0,0 +1,49 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tetris::lib::{Board, Cell, ExternalBot, PieceSet, Plan, Position};
+
+fn position(piece: char, next: char) -> Position {
+    let set = PieceSet::standard();
+    Position {
+        board: Board::new(10, 20),
+        piece: set.find(piece).unwrap(),
+        queue: vec![set.find(next).unwrap()],
+        hold: true,
+        combo: 0,
+        back_to_back: false,
+    }
+}
+
+fn suggestion(bot: &mut ExternalBot, turn: u64) -> Plan {
+    let started = Instant::now();
+    loop {
+        if let Some((planned, plan)) = bot.plans().pop() {
+            assert_eq!(planned, turn);
+            return plan;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "the bot didn't answer");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn the_stub_bot_plays_the_piece_in_play() {
+    let mut bot = ExternalBot::spawn(&[env!("CARGO_BIN_EXE_stub_bot").to_string()]).unwrap();
+
+    // asked before the bot is ready, so this goes through info, rules and ready first
+    bot.request(0, &position('I', 'O'));
+    let plan = suggestion(&mut bot, 0);
+    assert!(!plan.hold);
+    assert_eq!(plan.target, vec![(0, 19), (1, 19), (2, 19), (3, 19)]);
+
+    // the i a hold would swap in goes over as the held piece, leaving the o first in the queue
+    let mut position = position('O', 'I');
+    for x in 0..4 {
+        position.board.set(x, 19, Cell::Garbage);
+    }
+    bot.request(1, &position);
+    let plan = suggestion(&mut bot, 1);
+    assert!(!plan.hold);
+    assert_eq!(plan.target, vec![(4, 18), (4, 19), (5, 18), (5, 19)]);
+}